@@ -9,8 +9,8 @@ use common::net::state::{Zip};
 //cmd: cargo run --example many_all --features net
 #[tokio::main]
 async fn main() {
-    let (tx, mut rx) = net::init_net(net::state::Protocol::ALL, SocketAddr::from_str("0.0.0.0:18888").unwrap()).await.unwrap();
-    let (tx1, mut rx1) = net::init_net(net::state::Protocol::ALL, SocketAddr::from_str("0.0.0.0:18889").unwrap()).await.unwrap();
+    let (tx, mut rx, _) = net::init_net(net::state::Protocol::ALL, SocketAddr::from_str("0.0.0.0:18888").unwrap(), net::state::ListenConf::default()).await.unwrap();
+    let (tx1, mut rx1, _) = net::init_net(net::state::Protocol::ALL, SocketAddr::from_str("0.0.0.0:18889").unwrap(), net::state::ListenConf::default()).await.unwrap();
     tokio::spawn(async move{
         let mut i = 0;
         while let Some(zip) = rx.recv().await {
@@ -6,9 +6,9 @@ use common::net::state::Zip;
 //cmd: cargo run --example many_tcp --features net
 #[tokio::main]
 async fn main() {
-    let (tx1, mut rx1) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18887").unwrap()).await.unwrap();
-    let (tx2, mut rx2) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18888").unwrap()).await.unwrap();
-    let (tx3, mut rx3) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18889").unwrap()).await.unwrap();
+    let (tx1, mut rx1, _) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18887").unwrap(), net::state::ListenConf::default()).await.unwrap();
+    let (tx2, mut rx2, _) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18888").unwrap(), net::state::ListenConf::default()).await.unwrap();
+    let (tx3, mut rx3, _) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18889").unwrap(), net::state::ListenConf::default()).await.unwrap();
     tokio::spawn(
         async move {
             while let Some(zip) = rx1.recv().await {
@@ -10,7 +10,7 @@ use exception::TransError;
 #[tokio::main]
 async fn main() {
     let tu = net::sdx::listen(net::state::Protocol::ALL, SocketAddr::from_str("0.0.0.0:18889").unwrap()).unwrap();
-    let (tx, mut rx) = net::sdx::run_by_tokio(tu).await.unwrap();
+    let (tx, mut rx, _) = net::sdx::run_by_tokio(tu, net::state::ListenConf::default()).await.unwrap();
     let mut i = 0;
     while let Some(zip) = rx.recv().await {
         match zip {
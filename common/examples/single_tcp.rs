@@ -6,7 +6,12 @@ use common::net::state::Zip;
 //cmd: cargo run --example single_tcp --features net
 #[tokio::main]
 async fn main() {
-    let (tx, mut rx) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18888").unwrap()).await.unwrap();
+    let (tx, mut rx, handle) = net::init_net(net::state::Protocol::TCP, SocketAddr::from_str("0.0.0.0:18888").unwrap(), net::state::ListenConf::default()).await.unwrap();
+    //ctrl_c：写完已提交的数据后关闭监听与连接，全部任务退出后rx返回None
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        handle.shutdown(true).await;
+    });
     while let Some(zip) = rx.recv().await {
         match zip {
             Zip::Data(ref package) => {
@@ -14,7 +19,7 @@ async fn main() {
                 let _ = tx.clone().send(zip).await;
             }
             Zip::Event(ref event) => {
-                println!("event type code = {}",event.get_type_code());
+                println!("association = {:?} - event = {:?}", event.get_association(), event.get_kind());
             }
         }
     }
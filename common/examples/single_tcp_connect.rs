@@ -0,0 +1,26 @@
+use common::net;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use bytes::Bytes;
+use common::net::state::{Association, Event, EventKind, Package, Protocol, Zip};
+
+//先启动 single_tcp 作为对端
+//cmd: cargo run --example single_tcp_connect --features net
+#[tokio::main]
+async fn main() {
+    let local_addr = SocketAddr::from_str("0.0.0.0:18890").unwrap();
+    let (tx, mut rx, _) = net::init_net(Protocol::TCP, local_addr, net::state::ListenConf::default()).await.unwrap();
+    let association = Association::new(local_addr, SocketAddr::from_str("127.0.0.1:18888").unwrap(), Protocol::TCP);
+    tx.send(Zip::build_event(Event::new(association.clone(), EventKind::Connected))).await.unwrap();
+    tx.send(Zip::build_data(Package::new(association, Bytes::from("hello")))).await.unwrap();
+    while let Some(zip) = rx.recv().await {
+        match zip {
+            Zip::Data(ref package) => {
+                println!("association = {:?} - data: {:?}", package.get_association(), package.get_data());
+            }
+            Zip::Event(ref event) => {
+                println!("association = {:?} - event = {:?}", event.get_association(), event.get_kind());
+            }
+        }
+    }
+}
@@ -9,7 +9,7 @@ use common::net::state::Zip;
 //cmd: cargo run --example single_udp --features net
 #[tokio::main]
 async fn main() {
-    let (tx, mut rx) = net::init_net(net::state::Protocol::UDP, SocketAddr::from_str("0.0.0.0:18888").unwrap()).await.unwrap();
+    let (tx, mut rx, _) = net::init_net(net::state::Protocol::UDP, SocketAddr::from_str("0.0.0.0:18888").unwrap(), net::state::ListenConf::default()).await.unwrap();
     let mut i = 0;
     while let Some(zip) = rx.recv().await {
         match zip {
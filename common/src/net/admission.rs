@@ -0,0 +1,217 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::net::registry::{Registry, Slot};
+
+/// 接入控制，配置于ListenConf.admission
+/// # Examples
+///
+///  ```yaml
+/// admission:
+///   max_connections: 10000 #TCP最大连接数 可选
+///   max_per_ip: 16 #单个对端IP最大TCP连接数 可选
+///   allow: [10.0.0.0/8, 192.168.1.10] #仅允许的对端网段 可选 为空时不限制，TCP/UDP均生效
+///   deny: [10.1.0.0/16] #拒绝的对端网段 可选 优先于allow，TCP/UDP均生效
+///  ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AdmissionConf {
+    max_connections: Option<usize>,
+    max_per_ip: Option<usize>,
+    #[serde(default)]
+    allow: Vec<Cidr>,
+    #[serde(default)]
+    deny: Vec<Cidr>,
+}
+
+///拒绝原因
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Reject {
+    //命中deny或不在allow内
+    Denied,
+    MaxConnections,
+    MaxPerIp,
+    //可信代理的PROXY头缺失、无效或读取超时
+    InvalidProxy,
+    //TLS握手失败或超时
+    Handshake,
+}
+
+impl AdmissionConf {
+    pub fn new(max_connections: Option<usize>, max_per_ip: Option<usize>, allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { max_connections, max_per_ip, allow, deny }
+    }
+
+    //是否配置了按对端IP的限制
+    pub(crate) fn per_ip(&self) -> bool {
+        self.max_per_ip.is_some() || !self.allow.is_empty() || !self.deny.is_empty()
+    }
+
+    //对端网段校验，TCP接入与UDP数据报共用
+    pub fn check_ip(&self, ip: &IpAddr) -> Result<(), Reject> {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return Err(Reject::Denied);
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|cidr| cidr.contains(ip)) {
+            return Err(Reject::Denied);
+        }
+        Ok(())
+    }
+
+    //TCP接入校验并预留名额：网段、总连接数、单IP连接数
+    //先预留再校验，预留数计入已登记的连接数，超出时随Slot释放；并发接入只会多拒绝，不会超出上限
+    pub(crate) fn reserve_tcp(&self, registry: &Registry, ip: &IpAddr) -> Result<Slot, Reject> {
+        self.check_ip(ip)?;
+        let slot = self.reserve(registry)?;
+        self.reserve_per_ip(registry, slot, ip)
+    }
+
+    //仅预留总连接数名额，经代理的连接在读取PROXY头后由reserve_ip校验客户端地址
+    pub(crate) fn reserve(&self, registry: &Registry) -> Result<Slot, Reject> {
+        let (slot, reserved) = registry.reserve();
+        if self.max_connections.is_some_and(|max| registry.len() + reserved > max) {
+            return Err(Reject::MaxConnections);
+        }
+        Ok(slot)
+    }
+
+    pub(crate) fn reserve_ip(&self, registry: &Registry, slot: Slot, ip: &IpAddr) -> Result<Slot, Reject> {
+        self.check_ip(ip)?;
+        self.reserve_per_ip(registry, slot, ip)
+    }
+
+    fn reserve_per_ip(&self, registry: &Registry, mut slot: Slot, ip: &IpAddr) -> Result<Slot, Reject> {
+        let reserved = slot.bind_ip(*ip);
+        if self.max_per_ip.is_some_and(|max| registry.count_by_ip(ip) + reserved > max) {
+            return Err(Reject::MaxPerIp);
+        }
+        Ok(slot)
+    }
+}
+
+/// 网段，如10.0.0.0/8、fe80::/10；不带前缀长度时表示单个地址
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+//IPv4映射的IPv6地址按IPv4匹配
+fn canonical(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+        IpAddr::V4(_) => *ip,
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            None => (s.trim(), None),
+            Some((addr, prefix)) => (addr, Some(prefix)),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|err| format!("invalid cidr {s}: {err}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            None => max,
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid cidr prefix {s}"))?,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Cidr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::sync::mpsc;
+
+    use crate::net::state::{Association, Protocol};
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr = Cidr::from_str("10.0.0.0/8").unwrap();
+        assert!(cidr.contains(&ip("10.200.1.1")));
+        assert!(cidr.contains(&ip("::ffff:10.0.0.1")));
+        assert!(!cidr.contains(&ip("11.0.0.1")));
+        assert!(Cidr::from_str("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+        assert!(Cidr::from_str("192.168.1.10").unwrap().contains(&ip("192.168.1.10")));
+        assert!(Cidr::from_str("fe80::/10").unwrap().contains(&ip("fe80::1")));
+        assert!(Cidr::from_str("10.0.0.0/33").is_err());
+        assert_eq!(Cidr::from_str("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn test_check() {
+        let conf = AdmissionConf::new(Some(2), Some(1),
+                                      vec![Cidr::from_str("10.0.0.0/8").unwrap()],
+                                      vec![Cidr::from_str("10.1.0.0/16").unwrap()]);
+        assert_eq!(conf.check_ip(&ip("10.0.0.1")), Ok(()));
+        assert_eq!(conf.check_ip(&ip("10.1.0.1")), Err(Reject::Denied));
+        assert_eq!(conf.check_ip(&ip("192.168.0.1")), Err(Reject::Denied));
+
+        let registry = Registry::default();
+        let local_addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let (tx, _rx) = mpsc::channel(1);
+        registry.insert(Association::new(local_addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::TCP), tx.clone());
+        assert_eq!(conf.reserve_tcp(&registry, &ip("10.0.0.1")).unwrap_err(), Reject::MaxPerIp);
+        assert_eq!(registry.reserved(), 0);
+        let slot = conf.reserve_tcp(&registry, &ip("10.0.0.2")).unwrap();
+        //预留中的名额计入上限
+        assert_eq!(conf.reserve_tcp(&registry, &ip("10.0.0.3")).unwrap_err(), Reject::MaxConnections);
+        registry.insert(Association::new(local_addr, SocketAddr::from_str("10.0.0.2:5060").unwrap(), Protocol::TCP), tx);
+        drop(slot);
+        assert_eq!(registry.reserved(), 0);
+        assert_eq!(conf.reserve_tcp(&registry, &ip("10.0.0.3")).unwrap_err(), Reject::MaxConnections);
+
+        //经代理：先预留总数，读取PROXY头后按客户端地址校验
+        let registry = Registry::default();
+        let slot = conf.reserve(&registry).unwrap();
+        let slot = conf.reserve_ip(&registry, slot, &ip("10.0.0.1")).unwrap();
+        let other = conf.reserve(&registry).unwrap();
+        assert_eq!(conf.reserve_ip(&registry, other, &ip("10.0.0.1")).unwrap_err(), Reject::MaxPerIp);
+        assert_eq!(conf.reserve_ip(&registry, conf.reserve(&registry).unwrap(), &ip("10.1.0.1")).unwrap_err(), Reject::Denied);
+        assert_eq!(registry.reserved(), 1);
+        drop(slot);
+        assert_eq!(registry.reserved(), 0);
+    }
+}
@@ -0,0 +1,275 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use bytes::Bytes;
+use dashmap::DashMap;
+use log::{debug, error};
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+
+use exception::{GlobalError, GlobalResult, TransError};
+use exception::code::net_err::NET_RESPONSE_TIMEOUT_ERROR_CODE;
+use crate::net::state::{Association, Event, EventKind, Package, Protocol, Zip, CHANNEL_BUFFER_SIZE};
+
+/// 请求/响应关联，接管init_net等返回的读写通道
+/// send_and_wait登记等待后发送请求，同一association上首个key匹配的Package作为响应返回，不再转交程序
+/// 其余数据与事件原样转交new返回的Receiver；超时、取消等待或连接关闭时自动清理等待项
+/// Receiver应持续读取：积压满CHANNEL_BUFFER_SIZE后未匹配的数据被丢弃(见unmatched_dropped)，响应匹配不受影响；
+/// 事件不丢弃，积压满时等待Receiver读取，期间暂停响应匹配
+#[derive(Debug, Clone)]
+pub struct NetClient {
+    output: Sender<Zip>,
+    pending: Arc<DashMap<Association, Vec<Pending>>>,
+    next_id: Arc<AtomicU64>,
+    unmatched_dropped: Arc<AtomicU64>,
+}
+
+/// 服务端同样可用于向对端发起请求并等待响应
+pub type NetServer = NetClient;
+
+struct Pending {
+    id: u64,
+    matcher: Box<dyn Fn(&Bytes) -> bool + Send + Sync>,
+    tx: oneshot::Sender<Package>,
+}
+
+impl std::fmt::Debug for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pending").field("id", &self.id).finish()
+    }
+}
+
+//等待结束(响应、超时或future被丢弃)时移除等待项
+struct PendingGuard<'a> {
+    pending: &'a DashMap<Association, Vec<Pending>>,
+    association: &'a Association,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.remove_if_mut(self.association, |_, waiters| {
+            waiters.retain(|waiter| waiter.id != self.id);
+            waiters.is_empty()
+        });
+    }
+}
+
+impl NetClient {
+    pub fn new(output: Sender<Zip>, mut input: Receiver<Zip>) -> (Self, Receiver<Zip>) {
+        let pending: Arc<DashMap<Association, Vec<Pending>>> = Arc::new(DashMap::new());
+        let (unmatched_tx, unmatched_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        let dispatch_pending = pending.clone();
+        let unmatched_dropped = Arc::new(AtomicU64::new(0));
+        let dispatch_dropped = unmatched_dropped.clone();
+        tokio::spawn(async move {
+            while let Some(zip) = input.recv().await {
+                let zip = match zip {
+                    Zip::Data(package) => match take_matched(&dispatch_pending, package) {
+                        None => continue,
+                        Some(package) => Zip::Data(package),
+                    },
+                    Zip::Event(event) => {
+                        //连接已关闭，等待方立即返回错误
+                        if terminal(&event) {
+                            dispatch_pending.remove(event.get_association());
+                        }
+                        Zip::Event(event)
+                    }
+                };
+                //事件承载连接状态，始终投递；数据不等待未匹配的接收方，避免其未读取时阻塞响应匹配
+                if let Zip::Event(_) = zip {
+                    if unmatched_tx.send(zip).await.is_err() {
+                        debug!("NetClient unmatched receiver dropped");
+                    }
+                    continue;
+                }
+                match unmatched_tx.try_send(zip) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(zip)) => {
+                        dispatch_dropped.fetch_add(1, Ordering::Relaxed);
+                        debug!("NetClient unmatched receiver full, dropped => {:?}", zip.get_association());
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        dispatch_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        });
+        (Self { output, pending, next_id: Arc::new(AtomicU64::new(0)), unmatched_dropped }, unmatched_rx)
+    }
+
+    pub async fn send(&self, zip: Zip) -> GlobalResult<()> {
+        self.output.send(zip).await.hand_log(|msg| error!("{msg}"))?;
+        Ok(())
+    }
+
+    /// 发送请求并等待key相同的响应；key_extractor同时用于提取请求与响应的key，如SIP的Call-ID+CSeq
+    pub async fn send_and_wait<K, F>(&self, association: Association, data: Bytes, key_extractor: F, timeout: Duration) -> GlobalResult<Package>
+    where
+        K: PartialEq + Send + Sync + 'static,
+        F: Fn(&Bytes) -> Option<K> + Send + Sync + 'static,
+    {
+        let key = key_extractor(&data)
+            .ok_or_else(|| GlobalError::new_sys_error("request key not found", |msg| error!("{msg}")))?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        let matcher = Box::new(move |incoming: &Bytes| key_extractor(incoming).as_ref() == Some(&key));
+        self.pending.entry(association.clone()).or_default().push(Pending { id, matcher, tx });
+        let _guard = PendingGuard { pending: &self.pending, association: &association, id };
+        self.send(Zip::build_data(Package::new(association.clone(), data))).await?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(package)) => Ok(package),
+            Ok(Err(_)) => Err(GlobalError::new_sys_error(&format!("connection closed while waiting for response: {:?}", association), |msg| error!("{msg}"))),
+            Err(_) => Err(GlobalError::new_biz_error(NET_RESPONSE_TIMEOUT_ERROR_CODE, &format!("response timeout: {:?}", association), |msg| error!("{msg}"))),
+        }
+    }
+
+    //等待中的请求数
+    pub fn pending(&self) -> usize {
+        self.pending.iter().map(|entry| entry.value().len()).sum()
+    }
+
+    //未匹配接收方已满或已释放而丢弃的数据数
+    pub fn unmatched_dropped(&self) -> u64 {
+        self.unmatched_dropped.load(Ordering::Relaxed)
+    }
+}
+
+//连接终止的事件；UDP等无连接协议的读写错误、限速与过载仅影响单个数据报，对端仍可响应
+fn terminal(event: &Event) -> bool {
+    match event.get_kind() {
+        EventKind::PeerClosed | EventKind::LocallyClosed | EventKind::Aborted | EventKind::ConnectError => true,
+        EventKind::ReadError(_) | EventKind::WriteError(_) => !matches!(event.get_association().get_protocol(), Protocol::UDP | Protocol::UNIXGRAM),
+        _ => false,
+    }
+}
+
+//交给首个匹配的等待方，没有匹配时返回原Package
+fn take_matched(pending: &DashMap<Association, Vec<Pending>>, package: Package) -> Option<Package> {
+    let waiter = {
+        let Some(mut waiters) = pending.get_mut(package.get_association()) else { return Some(package); };
+        let Some(index) = waiters.iter().position(|waiter| (waiter.matcher)(package.get_data())) else { return Some(package); };
+        waiters.swap_remove(index)
+    };
+    //等待方已超时，按未匹配转交
+    waiter.tx.send(package).err()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn call_id(data: &Bytes) -> Option<String> {
+        std::str::from_utf8(data).ok()?.split(':').next().map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn test_send_and_wait() {
+        let addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let association = Association::new(addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::UDP);
+        let (output_tx, mut output_rx) = mpsc::channel(8);
+        let (input_tx, input_rx) = mpsc::channel(8);
+        let (client, mut unmatched) = NetClient::new(output_tx, input_rx);
+
+        //对端：回复请求，并先发送一条无关数据
+        let peer_input = input_tx.clone();
+        tokio::spawn(async move {
+            while let Some(Zip::Data(package)) = output_rx.recv().await {
+                let association = package.get_association().clone();
+                peer_input.send(Zip::build_data(Package::new(association.clone(), Bytes::from("other:1")))).await.unwrap();
+                let reply = format!("{}:200", call_id(package.get_data()).unwrap());
+                peer_input.send(Zip::build_data(Package::new(association, Bytes::from(reply)))).await.unwrap();
+            }
+        });
+        let response = client.send_and_wait(association.clone(), Bytes::from("abc:INVITE"), call_id, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.get_data(), &Bytes::from("abc:200"));
+        match unmatched.recv().await.unwrap() {
+            Zip::Data(package) => assert_eq!(package.get_data(), &Bytes::from("other:1")),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(client.pending(), 0);
+
+        //超时与连接关闭后等待项被清理
+        let (output_tx, _output_rx) = mpsc::channel(8);
+        let (input_tx, input_rx) = mpsc::channel(8);
+        let (client, _unmatched) = NetClient::new(output_tx, input_rx);
+        assert!(client.send_and_wait(association.clone(), Bytes::from("t:1"), call_id, Duration::from_millis(50)).await.is_err());
+        assert_eq!(client.pending(), 0);
+        let waiter = client.clone();
+        let wait_association = association.clone();
+        let wait = tokio::spawn(async move {
+            waiter.send_and_wait(wait_association, Bytes::from("c:1"), call_id, Duration::from_secs(5)).await
+        });
+        while client.pending() == 0 {
+            tokio::task::yield_now().await;
+        }
+        input_tx.send(Zip::build_event(Event::new(association, EventKind::PeerClosed))).await.unwrap();
+        assert!(wait.await.unwrap().is_err());
+        assert_eq!(client.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_keeps_waiting() {
+        let addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let association = Association::new(addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::UDP);
+        let (output_tx, mut output_rx) = mpsc::channel(8);
+        let (input_tx, input_rx) = mpsc::channel(8);
+        let (client, mut unmatched) = NetClient::new(output_tx, input_rx);
+
+        //对端：先触发一次限速(单个数据报被丢弃)，再回复请求
+        tokio::spawn(async move {
+            while let Some(Zip::Data(package)) = output_rx.recv().await {
+                let association = package.get_association().clone();
+                input_tx.send(Zip::build_event(Event::new(association.clone(), EventKind::RateLimited))).await.unwrap();
+                input_tx.send(Zip::build_event(Event::new(association.clone(), EventKind::WriteError(std::io::ErrorKind::Other)))).await.unwrap();
+                input_tx.send(Zip::build_data(Package::new(association, Bytes::from("abc:200")))).await.unwrap();
+            }
+        });
+        let response = client.send_and_wait(association, Bytes::from("abc:INVITE"), call_id, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.get_data(), &Bytes::from("abc:200"));
+        match unmatched.recv().await.unwrap() {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::RateLimited),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_full() {
+        let addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let association = Association::new(addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::UDP);
+        let (output_tx, mut output_rx) = mpsc::channel(8);
+        let (input_tx, input_rx) = mpsc::channel(8);
+        //未匹配的接收方不读取
+        let (client, mut unmatched) = NetClient::new(output_tx, input_rx);
+
+        tokio::spawn(async move {
+            while let Some(Zip::Data(package)) = output_rx.recv().await {
+                let association = package.get_association().clone();
+                for _ in 0..=CHANNEL_BUFFER_SIZE {
+                    input_tx.send(Zip::build_data(Package::new(association.clone(), Bytes::from("other:1")))).await.unwrap();
+                }
+                input_tx.send(Zip::build_data(Package::new(association.clone(), Bytes::from("abc:200")))).await.unwrap();
+                input_tx.send(Zip::build_event(Event::new(association, EventKind::PeerClosed))).await.unwrap();
+            }
+        });
+        let response = client.send_and_wait(association, Bytes::from("abc:INVITE"), call_id, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.get_data(), &Bytes::from("abc:200"));
+        assert_eq!(client.unmatched_dropped(), 1);
+
+        //积压满时事件等待读取，不丢弃
+        for _ in 0..CHANNEL_BUFFER_SIZE {
+            assert!(matches!(unmatched.recv().await, Some(Zip::Data(_))));
+        }
+        match tokio::time::timeout(Duration::from_secs(5), unmatched.recv()).await.unwrap().unwrap() {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::PeerClosed),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(client.unmatched_dropped(), 1);
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::error;
+
+use exception::{GlobalError, GlobalResult};
+
+pub const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+pub const CRLF: &[u8] = b"\r\n";
+pub const DOUBLE_CRLF: &[u8] = b"\r\n\r\n";
+
+/// TCP流帧编解码：每个连接持有独立的读缓冲，编解码器在同一监听下共享
+/// decode:从缓冲中切出一个完整消息，数据不足时返回None，等待下次读取
+/// encode:写入socket前将消息编码至dst
+/// decode_from:增量解码，scanned为连接持有的已查找偏移，按分隔符查找的编解码器据此跳过已检查的数据，默认同decode
+pub trait FrameCodec: Send + Sync + Debug {
+    fn decode(&self, src: &mut BytesMut) -> GlobalResult<Option<Bytes>>;
+    fn encode(&self, item: Bytes, dst: &mut BytesMut) -> GlobalResult<()>;
+
+    fn decode_from(&self, src: &mut BytesMut, scanned: &mut usize) -> GlobalResult<Option<Bytes>> {
+        let _ = scanned;
+        self.decode(src)
+    }
+}
+
+fn frame_too_large(len: usize, max_frame_size: usize) -> GlobalError {
+    GlobalError::new_sys_error(&format!("frame size {len} exceeds the limit of {max_frame_size}"), |msg| error!("{msg}"))
+}
+
+/// 原样透传：一次读取即一个消息，与未分帧时的行为一致
+#[derive(Debug, Default, Clone)]
+pub struct RawCodec;
+
+impl FrameCodec for RawCodec {
+    fn decode(&self, src: &mut BytesMut) -> GlobalResult<Option<Bytes>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(src.split().freeze()))
+    }
+
+    fn encode(&self, item: Bytes, dst: &mut BytesMut) -> GlobalResult<()> {
+        dst.extend_from_slice(&item);
+        Ok(())
+    }
+}
+
+/// 长度前缀：大端序length_field_len(1/2/4/8)字节表示消息体长度，解码后去除前缀
+#[derive(Debug, Clone)]
+pub struct LengthCodec {
+    length_field_len: usize,
+    max_frame_size: usize,
+}
+
+impl LengthCodec {
+    pub fn new(length_field_len: usize, max_frame_size: usize) -> Self {
+        assert!(matches!(length_field_len, 1 | 2 | 4 | 8), "length_field_len must be one of 1,2,4,8");
+        Self { length_field_len, max_frame_size }
+    }
+}
+
+impl Default for LengthCodec {
+    fn default() -> Self {
+        Self::new(4, MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameCodec for LengthCodec {
+    fn decode(&self, src: &mut BytesMut) -> GlobalResult<Option<Bytes>> {
+        if src.len() < self.length_field_len {
+            return Ok(None);
+        }
+        let len = (&src[..self.length_field_len]).get_uint(self.length_field_len) as usize;
+        if len > self.max_frame_size {
+            return Err(frame_too_large(len, self.max_frame_size));
+        }
+        if src.len() < self.length_field_len + len {
+            src.reserve(self.length_field_len + len - src.len());
+            return Ok(None);
+        }
+        src.advance(self.length_field_len);
+        Ok(Some(src.split_to(len).freeze()))
+    }
+
+    fn encode(&self, item: Bytes, dst: &mut BytesMut) -> GlobalResult<()> {
+        let len = item.len();
+        if len > self.max_frame_size || (self.length_field_len < 8 && len >> (self.length_field_len * 8) != 0) {
+            return Err(frame_too_large(len, self.max_frame_size));
+        }
+        dst.reserve(self.length_field_len + len);
+        dst.put_uint(len as u64, self.length_field_len);
+        dst.extend_from_slice(&item);
+        Ok(())
+    }
+}
+
+/// 分隔符：按delimiter切分消息，解码后去除分隔符，编码时追加分隔符
+#[derive(Debug, Clone)]
+pub struct DelimiterCodec {
+    delimiter: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl DelimiterCodec {
+    pub fn new(delimiter: &[u8], max_frame_size: usize) -> Self {
+        assert!(!delimiter.is_empty(), "delimiter must not be empty");
+        Self { delimiter: delimiter.to_vec(), max_frame_size }
+    }
+
+    pub fn crlf() -> Self {
+        Self::new(CRLF, MAX_FRAME_SIZE)
+    }
+
+    pub fn double_crlf() -> Self {
+        Self::new(DOUBLE_CRLF, MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameCodec for DelimiterCodec {
+    fn decode(&self, src: &mut BytesMut) -> GlobalResult<Option<Bytes>> {
+        self.decode_from(src, &mut 0)
+    }
+
+    fn decode_from(&self, src: &mut BytesMut, scanned: &mut usize) -> GlobalResult<Option<Bytes>> {
+        match find_from(src, &self.delimiter, scanned) {
+            Some(index) => {
+                if index > self.max_frame_size {
+                    return Err(frame_too_large(index, self.max_frame_size));
+                }
+                let frame = src.split_to(index).freeze();
+                src.advance(self.delimiter.len());
+                *scanned = 0;
+                Ok(Some(frame))
+            }
+            None if src.len() > self.max_frame_size + self.delimiter.len() => {
+                Err(frame_too_large(src.len(), self.max_frame_size))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&self, item: Bytes, dst: &mut BytesMut) -> GlobalResult<()> {
+        dst.reserve(item.len() + self.delimiter.len());
+        dst.extend_from_slice(&item);
+        dst.extend_from_slice(&self.delimiter);
+        Ok(())
+    }
+}
+
+/// SIP/HTTP风格：头部以\r\n\r\n结束，按Content-Length(SIP紧凑形式 l)截取消息体
+/// 解码结果为完整消息(头部+消息体)；消息前的空行(CRLF保活)直接丢弃
+#[derive(Debug, Clone)]
+pub struct ContentLengthCodec {
+    max_frame_size: usize,
+}
+
+impl ContentLengthCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+}
+
+impl Default for ContentLengthCodec {
+    fn default() -> Self {
+        Self::new(MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameCodec for ContentLengthCodec {
+    fn decode(&self, src: &mut BytesMut) -> GlobalResult<Option<Bytes>> {
+        self.decode_from(src, &mut 0)
+    }
+
+    fn decode_from(&self, src: &mut BytesMut, scanned: &mut usize) -> GlobalResult<Option<Bytes>> {
+        while src.starts_with(CRLF) {
+            src.advance(CRLF.len());
+            *scanned = scanned.saturating_sub(CRLF.len());
+        }
+        let header_len = match find_from(src, DOUBLE_CRLF, scanned) {
+            None if src.len() > self.max_frame_size => {
+                return Err(frame_too_large(src.len(), self.max_frame_size));
+            }
+            None => { return Ok(None); }
+            Some(index) => index + DOUBLE_CRLF.len(),
+        };
+        //Content-Length由对端提供，先与上限比较再相加，避免溢出
+        let body_len = content_length(&src[..header_len])?;
+        if body_len > self.max_frame_size {
+            return Err(frame_too_large(body_len, self.max_frame_size));
+        }
+        let frame_len = header_len + body_len;
+        if frame_len > self.max_frame_size {
+            return Err(frame_too_large(frame_len, self.max_frame_size));
+        }
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+        *scanned = 0;
+        Ok(Some(src.split_to(frame_len).freeze()))
+    }
+
+    fn encode(&self, item: Bytes, dst: &mut BytesMut) -> GlobalResult<()> {
+        dst.extend_from_slice(&item);
+        Ok(())
+    }
+}
+
+//解析头部中的Content-Length，缺省为0
+fn content_length(header: &[u8]) -> GlobalResult<usize> {
+    for line in header.split(|b| *b == b'\n') {
+        let Some(colon) = line.iter().position(|b| *b == b':') else { continue; };
+        let name = line[..colon].trim_ascii();
+        if name.eq_ignore_ascii_case(b"content-length") || name.eq_ignore_ascii_case(b"l") {
+            let value = std::str::from_utf8(line[colon + 1..].trim_ascii())
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .ok_or_else(|| GlobalError::new_sys_error("invalid Content-Length header", |msg| error!("{msg}")))?;
+            return Ok(value);
+        }
+    }
+    Ok(0)
+}
+
+//从scanned处继续查找，回退pattern.len()-1字节以覆盖跨读取边界的匹配；未找到时更新scanned，找到时scanned停在匹配处
+fn find_from(src: &[u8], pattern: &[u8], scanned: &mut usize) -> Option<usize> {
+    let start = scanned.saturating_sub(pattern.len() - 1).min(src.len());
+    match src[start..].windows(pattern.len()).position(|window| window == pattern) {
+        Some(index) => {
+            *scanned = start + index;
+            Some(start + index)
+        }
+        None => {
+            *scanned = src.len();
+            None
+        }
+    }
+}
+
+pub fn raw() -> Arc<dyn FrameCodec> {
+    Arc::new(RawCodec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(codec: &dyn FrameCodec, src: &mut BytesMut) -> Vec<Bytes> {
+        let mut frames = Vec::new();
+        while let Some(frame) = codec.decode(src).unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_length_codec() {
+        let codec = LengthCodec::new(2, 1024);
+        let mut dst = BytesMut::new();
+        codec.encode(Bytes::from_static(b"hello"), &mut dst).unwrap();
+        codec.encode(Bytes::from_static(b"world!"), &mut dst).unwrap();
+        assert_eq!(&dst[..2], &[0u8, 5]);
+
+        let mut src = BytesMut::from(&dst[..4]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&dst[4..]);
+        assert_eq!(decode_all(&codec, &mut src), vec![Bytes::from_static(b"hello"), Bytes::from_static(b"world!")]);
+        assert!(src.is_empty());
+
+        let mut src = BytesMut::from(&[0x10u8, 0x00][..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_delimiter_codec() {
+        let codec = DelimiterCodec::crlf();
+        let mut src = BytesMut::from(&b"OPTIONS\r\nINFO\r\nPART"[..]);
+        assert_eq!(decode_all(&codec, &mut src), vec![Bytes::from_static(b"OPTIONS"), Bytes::from_static(b"INFO")]);
+        assert_eq!(&src[..], b"PART");
+
+        let codec = DelimiterCodec::double_crlf();
+        let mut src = BytesMut::from(&b"A: 1\r\nB: 2\r\n\r\nC: 3\r\n"[..]);
+        assert_eq!(decode_all(&codec, &mut src), vec![Bytes::from_static(b"A: 1\r\nB: 2")]);
+
+        let codec = DelimiterCodec::new(b"\n", 4);
+        let mut src = BytesMut::from(&b"toolong"[..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_content_length_codec() {
+        let codec = ContentLengthCodec::default();
+        let first = "INVITE sip:34020000001320000001@3402000000 SIP/2.0\r\nContent-Length: 4\r\n\r\nv=0\n";
+        let second = "SIP/2.0 200 OK\r\nl: 0\r\n\r\n";
+        let mut src = BytesMut::from(&b"\r\n\r\n"[..]);
+        src.extend_from_slice(&first.as_bytes()[..30]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&first.as_bytes()[30..first.len() - 2]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        src.extend_from_slice(&first.as_bytes()[first.len() - 2..]);
+        src.extend_from_slice(second.as_bytes());
+        assert_eq!(decode_all(&codec, &mut src), vec![Bytes::from(first), Bytes::from(second)]);
+        assert!(src.is_empty());
+
+        let mut src = BytesMut::from(&b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"[..]);
+        assert!(codec.decode(&mut src).is_err());
+        let mut src = BytesMut::from(format!("GET / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX).as_bytes());
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_decode_from() {
+        let codec = ContentLengthCodec::default();
+        let message = "SIP/2.0 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let mut src = BytesMut::new();
+        let mut scanned = 0;
+        //逐字节到达，分隔符跨读取边界
+        for byte in message.as_bytes() {
+            assert!(codec.decode_from(&mut src, &mut scanned).unwrap().is_none());
+            src.extend_from_slice(&[*byte]);
+        }
+        assert_eq!(codec.decode_from(&mut src, &mut scanned).unwrap(), Some(Bytes::from(message)));
+        assert_eq!(scanned, 0);
+
+        let codec = DelimiterCodec::double_crlf();
+        let mut src = BytesMut::from(&b"A: 1\r\n\r"[..]);
+        let mut scanned = 0;
+        assert!(codec.decode_from(&mut src, &mut scanned).unwrap().is_none());
+        assert_eq!(scanned, src.len());
+        src.extend_from_slice(b"\nB");
+        assert_eq!(codec.decode_from(&mut src, &mut scanned).unwrap(), Some(Bytes::from_static(b"A: 1")));
+        assert_eq!(&src[..], b"B");
+    }
+}
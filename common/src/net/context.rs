@@ -0,0 +1,136 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// 连接上下文：程序按类型附加到存活连接的数据，如设备ID、鉴权状态
+/// 由Registry::context获取，TCP/TLS/UNIX读取的Package携带同一上下文(Package::get_context)
+/// 连接断开时随登记项移除并清空，已持有的副本随之读不到数据
+#[derive(Clone, Default)]
+pub struct Context {
+    values: Arc<RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>>,
+}
+
+impl Context {
+    //同类型已存在时替换，返回原值
+    pub fn insert<T: Any + Send + Sync>(&self, value: T) -> Option<Arc<T>> {
+        let old = self.values.write().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(TypeId::of::<T>(), Arc::new(value));
+        old.and_then(|old| old.downcast().ok())
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let value = self.values.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&TypeId::of::<T>()).cloned();
+        value.and_then(|value| value.downcast().ok())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let old = self.values.write().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&TypeId::of::<T>());
+        old.and_then(|old| old.downcast().ok())
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.values.read().unwrap_or_else(|poisoned| poisoned.into_inner()).contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.read().unwrap_or_else(|poisoned| poisoned.into_inner()).is_empty()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.values.write().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+    }
+}
+
+impl std::fmt::Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let len = self.values.read().map(|values| values.len()).unwrap_or_default();
+        f.debug_struct("Context").field("len", &len).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpStream, UdpSocket, UnixStream};
+    use tokio::sync::mpsc::Receiver;
+    use tokio::time;
+
+    use crate::net;
+    use crate::net::state::{EventKind, ListenConf, Package, Protocol, Zip};
+    use super::*;
+
+    async fn recv(rx: &mut Receiver<Zip>) -> Zip {
+        time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap()
+    }
+
+    async fn recv_package(rx: &mut Receiver<Zip>) -> Package {
+        match recv(rx).await {
+            Zip::Data(package) => package,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let context = Context::default();
+        assert!(context.insert(7u32).is_none());
+        assert_eq!(context.insert(42u32).as_deref(), Some(&7));
+        assert_eq!(context.get::<u32>().as_deref(), Some(&42));
+        //按类型存取，类型不同时读不到
+        assert!(context.get::<u64>().is_none());
+        assert!(!context.contains::<i32>());
+        assert!(context.remove::<u64>().is_none());
+        assert_eq!(context.remove::<u32>().as_deref(), Some(&42));
+        assert!(context.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_package_context() {
+        //TCP：数据携带登记的上下文，连接关闭后清空
+        let local_addr = SocketAddr::from_str("127.0.0.1:38446").unwrap();
+        let (_tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, ListenConf::default()).await.unwrap();
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let association = match recv(&mut rx).await {
+            Zip::Event(event) => event.get_association().clone(),
+            other => panic!("unexpected {other:?}"),
+        };
+        let context = handle.registry().context(&association).unwrap();
+        context.insert(String::from("device"));
+        peer.write_all(b"hi").await.unwrap();
+        let package = recv_package(&mut rx).await;
+        assert_eq!(package.get_context().as_ref().unwrap().get::<String>().unwrap().as_str(), "device");
+        drop(peer);
+        match recv(&mut rx).await {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::PeerClosed),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(context.is_empty());
+        assert!(package.get_context().as_ref().unwrap().is_empty());
+        assert!(handle.registry().context(&association).is_none());
+
+        //UNIX流式连接与TCP一致
+        let path = std::env::temp_dir().join(format!("net-context-{}.sock", std::process::id()));
+        let (_tx, mut rx, handle) = net::init_unix(Protocol::UNIX, &path, ListenConf::default()).await.unwrap();
+        let mut peer = UnixStream::connect(&path).await.unwrap();
+        let association = match recv(&mut rx).await {
+            Zip::Event(event) => event.get_association().clone(),
+            other => panic!("unexpected {other:?}"),
+        };
+        handle.registry().context(&association).unwrap().insert(1u8);
+        peer.write_all(b"hi").await.unwrap();
+        assert_eq!(recv_package(&mut rx).await.get_context().as_ref().unwrap().get::<u8>().as_deref(), Some(&1));
+        time::timeout(Duration::from_secs(5), handle.shutdown(false)).await.unwrap();
+
+        //UDP无连接，不登记上下文，数据不携带
+        let local_addr = SocketAddr::from_str("127.0.0.1:38447").unwrap();
+        let (_tx, mut rx, handle) = net::init_net(Protocol::UDP, local_addr, ListenConf::default()).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.send_to(b"hi", local_addr).await.unwrap();
+        let package = recv_package(&mut rx).await;
+        assert!(package.get_context().is_none());
+        assert!(handle.registry().context(package.get_association()).is_none());
+    }
+}
@@ -1,53 +1,94 @@
 use std::net::SocketAddr;
 use std::sync::{Arc};
+use std::time::Duration;
+use socket2::SockRef;
 use tokio::{io};
-use crate::net::state::{Zip, Gate, GateListener, GateAccept, Protocol, CHANNEL_BUFFER_SIZE, TCP_HANDLE_MAP};
-use crate::net::{tcp, udp};
-use log::{error, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use crate::net::state::{Zip, Gate, ListenConf, GateListener, GateAccept, Protocol, EventKind, Association, Event};
+use crate::net::{tcp, tls, udp};
+use crate::net::inbound::Inbound;
+use crate::net::proxy::ProxyPeers;
+use crate::net::handle::{NetHandle, Signal, Stage};
+use log::{debug, error, warn};
 use crate::exception::{GlobalResult, TransError};
 use tokio::sync::{mpsc, oneshot};
 use tokio::sync::mpsc::{Receiver, Sender};
 use exception::GlobalError;
+use exception::code::net_err::NET_UNROUTABLE_ERROR_CODE;
 
 //启动监听并返回读写句柄
-pub async fn listen(protocol: Protocol, local_addr: SocketAddr, tx: oneshot::Sender<GateListener>) -> GlobalResult<(Sender<Zip>, Receiver<Zip>)> {
+pub async fn listen(protocol: Protocol, local_addr: SocketAddr, conf: Arc<ListenConf>, tx: oneshot::Sender<GateListener>) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
     //socket 读数据通道 input
-    let (input_tx, input_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+    let (input_tx, input_rx) = mpsc::channel(conf.channel_capacity());
+    let (output_tx, handle) = listen_into(protocol, local_addr, conf, input_tx, tx).await?;
+    Ok((output_tx, input_rx, handle))
+}
+
+//启动监听，读取的数据投递到给定的input通道，多个监听可共用
+pub(crate) async fn listen_into(protocol: Protocol, local_addr: SocketAddr, conf: Arc<ListenConf>, input_tx: Sender<Zip>, tx: oneshot::Sender<GateListener>) -> GlobalResult<(Sender<Zip>, NetHandle)> {
+    let (handle, signal) = NetHandle::new(local_addr, protocol.clone());
+    let capacity = conf.channel_capacity();
     //socket 写数据通道 output
-    let (output_tx, output_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+    let (output_tx, output_rx) = mpsc::channel(capacity);
+    handle.stats().watch_channels(input_tx.downgrade(), output_tx.downgrade());
+    let input_tx = Inbound::new(input_tx, *conf.get_overload(), capacity, handle.stats().clone())
+        .with_rate_limits(conf.get_rate_limits())?;
     match protocol {
         Protocol::TCP => {
-            let gate = Gate::new(local_addr, input_tx, output_rx);
+            let gate = Gate::new(local_addr, input_tx, output_rx, conf, signal, handle.registry().clone(), None);
             let listener = tcp::listen(gate).await?;
             tx.send(listener).map_err(|_err|GlobalError::new_sys_error("net io listen err:channel has drop",|msg| error!("{msg}")))?;
         }
+        Protocol::TLS => {
+            let tls = tls::context(conf.get_tls().as_ref())?;
+            let gate = Gate::new(local_addr, input_tx, output_rx, conf, signal, handle.registry().clone(), Some(tls));
+            let listener = tcp::listen_tls(gate).await?;
+            tx.send(listener).map_err(|_err|GlobalError::new_sys_error("net io listen err:channel has drop",|msg| error!("{msg}")))?;
+        }
         Protocol::UDP => {
-            let gate = Gate::new(local_addr, input_tx, output_rx);
+            let gate = Gate::new(local_addr, input_tx, output_rx, conf, signal, handle.registry().clone(), None);
             let listener = udp::listen(gate).await?;
+            if let Some(udp_socket) = listener.udp_socket() {
+                handle.attach_udp(udp_socket)?;
+            }
             tx.send(listener).map_err(|_err|GlobalError::new_sys_error("net io listen err:channel has drop",|msg| error!("{msg}")))?;
         }
         Protocol::ALL => {
-            let (tw_tx, tw_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-            let tgate = Gate::new(local_addr, input_tx.clone(), tw_rx);
+            let (tw_tx, tw_rx) = mpsc::channel(capacity);
+            let tgate = Gate::new(local_addr, input_tx.clone(), tw_rx, conf.clone(), signal.clone(), handle.registry().clone(), None);
             let tcp_listener = tcp::listen(tgate).await?;
-            let (uw_tx, uw_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-            let ugate = Gate::new(local_addr, input_tx.clone(), uw_rx);
+            let (uw_tx, uw_rx) = mpsc::channel(capacity);
+            let ugate = Gate::new(local_addr, input_tx.clone(), uw_rx, conf, signal.clone(), handle.registry().clone(), None);
+            let classify_input = input_tx.clone();
             let udp_listener = udp::listen(ugate).await?;
+            if let Some(udp_socket) = udp_listener.udp_socket() {
+                handle.attach_udp(udp_socket)?;
+            }
             let gate_listener = GateListener::build_all(tcp_listener,udp_listener);
             tx.send(gate_listener).map_err(|_err|GlobalError::new_sys_error("net io listen err:channel has drop",|msg| error!("{msg}")))?;
-            classify(output_rx, tw_tx, uw_tx);
+            classify(output_rx, tw_tx, uw_tx, classify_input, signal);
+        }
+        Protocol::UNIX | Protocol::UNIXGRAM => {
+            return Err(GlobalError::new_sys_error("UNIX listener requires a path, use init_unix instead", |msg| error!("{msg}")));
         }
     }
-    Ok((output_tx, input_rx))
+    Ok((output_tx, handle))
 }
 
-pub fn classify(mut output: Receiver<Zip>, tw_tx: Sender<Zip>, uw_tx: Sender<Zip>) {
+//ALL监听按association协议分发输出；协议不是TCP/UDP时按NET_UNROUTABLE_ERROR_CODE记录，并通知WriteError(Unsupported)
+pub fn classify(mut output: Receiver<Zip>, tw_tx: Sender<Zip>, uw_tx: Sender<Zip>, input: Inbound, mut signal: Signal) {
     tokio::spawn(async move {
-        while let Some(zip) = output.recv().await {
-            match zip.get_association_protocol() {
-                &Protocol::UDP => { let _ = uw_tx.clone().send(zip).await.hand_log(|msg| error!("{msg}")); }
-                &Protocol::TCP => { let _ = tw_tx.clone().send(zip).await.hand_log(|msg| error!("{msg}")); }
-                &Protocol::ALL => { warn!("全协议发送????") }
+        while let Some(zip) = recv_output(&mut output, &mut signal).await {
+            match *zip.get_association_protocol() {
+                Protocol::UDP => { let _ = uw_tx.clone().send(zip).await.hand_log(|msg| error!("{msg}")); }
+                Protocol::TCP => { let _ = tw_tx.clone().send(zip).await.hand_log(|msg| error!("{msg}")); }
+                _ => {
+                    let association = zip.get_association();
+                    let _ = GlobalError::new_biz_error(NET_UNROUTABLE_ERROR_CODE, &format!("no listener for {:?} on {}", association.get_protocol(), association.get_local_addr()), |msg| error!("{msg}"));
+                    let event = Zip::build_event(Event::new(association, EventKind::WriteError(io::ErrorKind::Unsupported)));
+                    let _ = input.send(event).await.hand_log(|msg| error!("{msg}"));
+                }
             }
         }
     });
@@ -59,63 +100,17 @@ pub async fn accept(rx: oneshot::Receiver<GateListener>, tx: Sender<GateAccept>)
         Ok(gate_listener) => {
             match gate_listener {
                 GateListener::Tcp(gate, listener) => {
-                    let sender = tx.clone();
-                    let local_addr = gate.get_local_addr().clone();
-                    let input = gate.get_input().clone();
-                    tokio::spawn(async move {
-                        loop {
-                            //给予每个对外发送数据tcp连接一个接收句柄，并将其对应的发送句柄保存起来
-                            let (lone_output_tx, lone_output_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-                            let gate1 = Gate::new(local_addr, input.clone(), lone_output_rx);
-                            let _ = tcp::accept(gate1, &listener, sender.clone(), lone_output_tx).await.hand_log(|msg| error!("{msg}"));
-                        }
-                    });
-                    tokio::spawn(async move {
-                        //接收对外输出信息，并根据Zip上的账单信息，发送到对应的TCP发送通道
-                        let mut receiver = gate.get_owned_output();
-                        while let Some(zip) = receiver.recv().await {
-                            let association = zip.get_association();
-                            match TCP_HANDLE_MAP.clone().get(&association) {
-                                None => {
-                                    warn!("【TCP】连接不存在 => {:?}",&association);
-                                }
-                                Some(lone_output_tx) => {
-                                    let _ = lone_output_tx.send(zip).await.hand_log(|msg| error!("{msg}"));
-                                }
-                            }
-                        }
-                    });
+                    run_tcp(gate, listener, tx.clone(), Protocol::TCP);
+                }
+                GateListener::Tls(gate, listener) => {
+                    run_tcp(gate, listener, tx.clone(), Protocol::TLS);
                 }
                 GateListener::Udp(gate, socket) => {
                     udp::accept(gate, socket, tx.clone()).await?;
                 }
-                GateListener::All((tcp_gate, tcp_listener), (udp_gate, udp_socket)) => {
-                    let sender = tx.clone();
-                    let local_addr = tcp_gate.get_local_addr().clone();
-                    let input = tcp_gate.get_input().clone();
-                    tokio::spawn(async move {
-                        loop {
-                            //给予每个对外发送数据tcp连接一个接收句柄，并将其对应的发送句柄保存起来
-                            let (lone_output_tx, lone_output_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-                            let gate1 = Gate::new(local_addr, input.clone(), lone_output_rx);
-                            let _ = tcp::accept(gate1, &tcp_listener, sender.clone(), lone_output_tx).await.hand_log(|msg| error!("{msg}"));
-                        }
-                    });
-                    tokio::spawn(async move {
-                        //接收对外输出信息，并根据Zip上的账单信息，发送到对应的TCP发送通道
-                        let mut receiver = tcp_gate.get_owned_output();
-                        while let Some(zip) = receiver.recv().await {
-                            let association = zip.get_association();
-                            match TCP_HANDLE_MAP.clone().get(&association) {
-                                None => {
-                                    warn!("【TCP】连接不存在 => {:?}",&association);
-                                }
-                                Some(lone_output_tx) => {
-                                    let _ = lone_output_tx.send(zip).await.hand_log(|msg| error!("{msg}"));
-                                }
-                            }
-                        }
-                    });
+                GateListener::All((tcp_gate, tcp_listener), udp) => {
+                    let (udp_gate, udp_socket) = *udp;
+                    run_tcp(tcp_gate, tcp_listener, tx.clone(), Protocol::TCP);
                     udp::accept(udp_gate, udp_socket, tx).await?;
                 }
             }
@@ -127,34 +122,158 @@ pub async fn accept(rx: oneshot::Receiver<GateListener>, tx: Sender<GateAccept>)
     Ok(())
 }
 
+//接收程序输出；关闭时Closing立即结束，Draining则关闭通道并返回已排队的数据
+pub(crate) async fn recv_output(output: &mut Receiver<Zip>, signal: &mut Signal) -> Option<Zip> {
+    tokio::select! {
+        zip = output.recv() => zip,
+        stage = signal.stopping() => {
+            match stage {
+                Stage::Draining => {
+                    output.close();
+                    output.recv().await
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+//开启TCP/TLS接入，并按Zip上的账单信息将对外输出分发到各连接
+fn run_tcp(gate: Gate, listener: TcpListener, accept_tx: Sender<GateAccept>, protocol: Protocol) {
+    let local_addr = *gate.get_local_addr();
+    let input = gate.get_input().clone();
+    let conf = gate.get_conf().clone();
+    let mut signal = gate.get_signal().clone();
+    let registry = gate.get_registry().clone();
+    let tls = gate.get_tls().clone();
+    let sender = accept_tx.clone();
+    let accept_input = input.clone();
+    let accept_conf = conf.clone();
+    let mut accept_signal = signal.clone();
+    let accept_registry = registry.clone();
+    let accept_tls = tls.clone();
+    let capacity = conf.channel_capacity();
+    let accept_protocol = protocol.clone();
+    tokio::spawn(async move {
+        loop {
+            //给予每个对外发送数据tcp连接一个接收句柄，并将其对应的发送句柄保存起来
+            let (lone_output_tx, lone_output_rx) = mpsc::channel(capacity);
+            let gate1 = Gate::new(local_addr, accept_input.clone(), lone_output_rx, accept_conf.clone(), accept_signal.clone(), accept_registry.clone(), accept_tls.clone());
+            tokio::select! {
+                res = tcp::accept(gate1, &listener, sender.clone(), lone_output_tx, accept_protocol.clone()) => {
+                    let _ = res.hand_log(|msg| error!("{msg}"));
+                }
+                _ = accept_signal.stopping() => {
+                    debug!("【{}】停止监听 => {:?}", accept_protocol.get_value(), local_addr);
+                    break;
+                }
+            }
+        }
+    });
+    tokio::spawn(async move {
+        //接收对外输出信息，并根据Zip上的账单信息，发送到对应的TCP发送通道
+        let mut receiver = gate.get_owned_output();
+        while let Some(zip) = recv_output(&mut receiver, &mut signal).await {
+            let association = zip.get_association();
+            let lone_output_tx = registry.get(&association);
+            match (lone_output_tx, zip) {
+                (None, Zip::Event(event)) if event.get_kind() == &EventKind::Connected => {
+                    //主动连接：先登记发送句柄，连接建立前的输出数据在通道中排队
+                    let (lone_output_tx, lone_output_rx) = mpsc::channel(capacity);
+                    registry.insert(association.clone(), lone_output_tx);
+                    let gate1 = Gate::new(local_addr, input.clone(), lone_output_rx, conf.clone(), signal.clone(), registry.clone(), tls.clone());
+                    let sender = accept_tx.clone();
+                    tokio::spawn(async move {
+                        let _ = tcp::connect(gate1, association, sender).await;
+                    });
+                }
+                (None, _) => {
+                    warn!("【TCP】连接不存在 => {:?}",&association);
+                }
+                (Some(_), Zip::Event(event)) if event.get_kind() == &EventKind::Connected => {
+                    debug!("【TCP】连接已存在 => {:?}",&association);
+                }
+                (Some(lone_output_tx), zip) => {
+                    let _ = lone_output_tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                }
+            }
+        }
+        //监听关闭：排在已提交数据之后通知各连接关闭；Closing时由写任务直接关闭
+        for (association, lone_output_tx) in registry.entries() {
+            //写任务已退出(如连接未完成登记)，直接移除
+            if lone_output_tx.send(Zip::build_event(Event::new(association.clone(), EventKind::LocallyClosed))).await.is_err() {
+                tcp::close(&registry, association, EventKind::LocallyClosed, &input).await;
+            }
+        }
+    });
+}
+
 pub async fn rw(mut rx: Receiver<GateAccept>) {
     while let Some(gate_accept) = rx.recv().await {
         match gate_accept {
             GateAccept::Tcp(gate, remote_addr, tcp_stream) => {
-                let (read, write) = io::split(tcp_stream);
-                let local_addr = gate.get_local_addr().clone();
-                let sender = gate.get_input().clone();
-                tokio::spawn(async move {
-                    let _ = tcp::read(read, local_addr, remote_addr, sender).await;
-                });
-                let receiver = gate.get_owned_output();
-                tokio::spawn(async move {
-                    let _ = tcp::write(write, receiver).await;
-                });
+                stream_rw(gate, remote_addr, Protocol::TCP, tcp_stream, |tcp_stream| reset(SockRef::from(tcp_stream)));
+            }
+            GateAccept::Tls(gate, remote_addr, tls_stream) => {
+                stream_rw(gate, remote_addr, Protocol::TLS, tls_stream, |tls_stream| reset(SockRef::from(tls_stream.get_ref().0)));
             }
             GateAccept::Udp(gate, udp_socket) => {
                 let local_addr = gate.get_local_addr().clone();
                 let sender = gate.get_input().clone();
+                let write_sender = sender.clone();
+                let read_signal = gate.get_signal().clone();
+                let write_signal = read_signal.clone();
+                let conf = gate.get_conf().clone();
                 let receiver = gate.get_owned_output();
+                let proxied = conf.get_proxy_protocol().as_ref().map(|_| ProxyPeers::default());
+                let write_proxied = proxied.clone();
                 let aus = Arc::new(udp_socket);
                 let ausc = aus.clone();
                 tokio::spawn(async move {
-                    let _ = udp::read(local_addr, &*aus, sender).await;
+                    let _ = udp::read(local_addr, &*aus, sender, read_signal, conf, None, proxied).await;
                 });
                 tokio::spawn(async move {
-                    let _ = udp::write(&*ausc, receiver).await;
+                    let _ = udp::write(&*ausc, receiver, write_sender, write_signal, write_proxied).await;
                 });
             }
         }
     }
-}
\ No newline at end of file
+}
+//Aborted时写任务取回读半部并合并为原连接，由abort设置SO_LINGER 0，释放后连接以RST关闭
+fn stream_rw<S>(gate: Gate, remote_addr: SocketAddr, protocol: Protocol, stream: S, abort: fn(&S))
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut read, mut write) = io::split(stream);
+    let association = Association::new(*gate.get_local_addr(), remote_addr, protocol);
+    let write_association = association.clone();
+    let sender = gate.get_input().clone();
+    let write_sender = sender.clone();
+    let conf = gate.get_conf().clone();
+    let write_conf = conf.clone();
+    let signal = gate.get_signal().clone();
+    let write_signal = signal.clone();
+    let registry = gate.get_registry().clone();
+    let write_registry = registry.clone();
+    let (write_done_tx, write_done_rx) = oneshot::channel();
+    let (read_half_tx, read_half_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        tcp::read(&mut read, association, sender, conf, signal, registry, write_done_rx).await;
+        let _ = read_half_tx.send(read);
+    });
+    let receiver = gate.get_owned_output();
+    tokio::spawn(async move {
+        let aborted = tcp::write(&mut write, write_association, receiver, write_sender, write_conf, write_signal, write_registry).await;
+        //写半部仍持有连接，读任务退出后交回读半部
+        drop(write_done_tx);
+        if aborted {
+            if let Ok(read) = read_half_rx.await {
+                abort(&read.unsplit(write));
+            }
+        }
+    });
+}
+
+fn reset(socket: SockRef) {
+    let _ = socket.set_linger(Some(Duration::ZERO)).hand_log(|msg| warn!("set linger failed: {msg}"));
+}
@@ -0,0 +1,142 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use log::error;
+use socket2::{SockRef, Socket};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, watch, Mutex};
+
+use exception::{GlobalError, GlobalResult, TransError};
+use crate::net::multicast::Membership;
+use crate::net::registry::Registry;
+use crate::net::state::Protocol;
+use crate::net::stats::Stats;
+
+/// 监听句柄，随读写通道由init_net/run_by_tokio返回
+/// 丢弃句柄不会停止监听；进程退出前(如收到TERM信号)调用shutdown关闭监听与连接
+#[derive(Debug)]
+pub struct NetHandle {
+    stage: Arc<watch::Sender<Stage>>,
+    done: Mutex<mpsc::Receiver<()>>,
+    registry: Registry,
+    stats: Stats,
+    //UDP socket的复制句柄，用于运行中调整组播；分片监听时每个分片一个，shutdown时释放
+    udp: std::sync::Mutex<Vec<Socket>>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stage {
+    Running,
+    //停止接入，写完已排队的输出数据后关闭连接
+    Draining,
+    //停止接入，立即关闭连接
+    Closing,
+}
+
+/// 关闭信号，随Gate分发到各任务；所有Signal释放即表示任务全部退出
+#[derive(Debug, Clone)]
+pub struct Signal {
+    stage: watch::Receiver<Stage>,
+    //持有发送端，保证watch不会因句柄丢弃而关闭
+    _keep: Arc<watch::Sender<Stage>>,
+    _done: mpsc::Sender<()>,
+}
+
+impl NetHandle {
+    pub(crate) fn new(local_addr: SocketAddr, protocol: Protocol) -> (Self, Signal) {
+        let (stage_tx, stage_rx) = watch::channel(Stage::Running);
+        let stage_tx = Arc::new(stage_tx);
+        let (done_tx, done_rx) = mpsc::channel(1);
+        let signal = Signal { stage: stage_rx, _keep: stage_tx.clone(), _done: done_tx };
+        let handle = Self {
+            stage: stage_tx,
+            done: Mutex::new(done_rx),
+            registry: Registry::default(),
+            stats: Stats::new(local_addr, protocol),
+            udp: std::sync::Mutex::new(Vec::new()),
+        };
+        (handle, signal)
+    }
+
+    pub(crate) fn attach_udp(&self, udp_socket: &UdpSocket) -> GlobalResult<()> {
+        let socket = SockRef::from(udp_socket).try_clone().hand_log(|msg| error!("{msg}"))?;
+        self.udp.lock().unwrap().push(socket);
+        Ok(())
+    }
+
+    /// 停止接入并关闭所有连接，每个连接通知一次LocallyClosed，等待读写任务全部退出后返回
+    /// drain:先写完已提交的输出数据再关闭连接
+    pub async fn shutdown(&self, drain: bool) {
+        let stage = if drain { Stage::Draining } else { Stage::Closing };
+        self.stage.send_if_modified(|current| {
+            if *current == Stage::Running || stage == Stage::Closing && *current != Stage::Closing {
+                *current = stage;
+                return true;
+            }
+            false
+        });
+        let mut done = self.done.lock().await;
+        let _ = done.recv().await;
+        self.udp.lock().unwrap().clear();
+    }
+
+    /// 运行中加入组播组，仅UDP/ALL监听可用
+    pub fn join_multicast(&self, membership: &Membership) -> GlobalResult<()> {
+        self.with_udp(|socket| membership.join(&SockRef::from(socket)))
+    }
+
+    /// 运行中退出组播组，仅UDP/ALL监听可用
+    pub fn leave_multicast(&self, membership: &Membership) -> GlobalResult<()> {
+        self.with_udp(|socket| membership.leave(&SockRef::from(socket)))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn udp_sockets(&self) -> usize {
+        self.udp.lock().unwrap().len()
+    }
+
+    //分片监听的每个socket均需调整，任一失败时返回错误
+    fn with_udp(&self, f: impl Fn(&Socket) -> std::io::Result<()>) -> GlobalResult<()> {
+        let sockets = self.udp.lock().unwrap();
+        if sockets.is_empty() {
+            return Err(GlobalError::new_sys_error("no udp socket on this listener or it has been shut down", |msg| error!("{msg}")));
+        }
+        for socket in sockets.iter() {
+            f(socket).hand_log(|msg| error!("{msg}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self.stage.borrow() == Stage::Running
+    }
+
+    //监听的连接登记表
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    //监听的收发统计
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+impl Signal {
+    pub fn is_running(&self) -> bool {
+        *self.stage.borrow() == Stage::Running
+    }
+
+    //等待停止监听
+    pub async fn stopping(&mut self) -> Stage {
+        match self.stage.wait_for(|stage| *stage != Stage::Running).await {
+            Ok(stage) => *stage,
+            Err(_) => Stage::Closing,
+        }
+    }
+
+    //等待立即关闭
+    pub async fn closing(&mut self) {
+        let _ = self.stage.wait_for(|stage| *stage == Stage::Closing).await;
+    }
+}
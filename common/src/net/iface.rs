@@ -0,0 +1,160 @@
+use std::ffi::CStr;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+/// 网卡的IPv4/IPv6地址(端口为0)，IPv6地址带scope_id
+pub fn interface_addrs(name: &str) -> io::Result<Vec<SocketAddr>> {
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut addrs = Vec::new();
+    let mut cursor = ifap;
+    while !cursor.is_null() {
+        //getifaddrs返回的链表在freeifaddrs前有效
+        let ifa = unsafe { &*cursor };
+        cursor = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        match unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                addrs.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))), 0));
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                addrs.push(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(sin6.sin6_addr.s6_addr), 0, 0, sin6.sin6_scope_id)));
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    Ok(addrs)
+}
+
+/// 监听地址，可嵌入程序配置：IP:端口、主机名:端口，或以if:前缀按网卡名选取地址
+/// # Examples
+///
+///  ```yaml
+/// addr: 0.0.0.0:5060 #IPv4地址
+/// addr: "[::]:5060" #IPv6地址，双栈与否见SocketOptions.only_v6
+/// addr: localhost:5060 #主机名，resolve时解析取首个地址
+/// addr: if:eth0:5060 #网卡eth0的首个IPv4地址
+/// addr: "if:[eth0]:5060" #网卡eth0的IPv6地址，优先全局地址，仅有link-local时带scope_id
+///  ```
+/// 主机名与网卡地址在resolve时读取；仅限定收发网卡而不限定地址时使用SocketOptions.interface
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ListenAddr {
+    Addr(SocketAddr),
+    Host { host: String, port: u16 },
+    Interface { name: String, v6: bool, port: u16 },
+}
+
+//网卡地址前缀
+const INTERFACE_PREFIX: &str = "if:";
+
+impl ListenAddr {
+    pub fn resolve(&self) -> io::Result<SocketAddr> {
+        let (name, v6, port) = match self {
+            ListenAddr::Addr(addr) => return Ok(*addr),
+            ListenAddr::Host { host, port } => {
+                return (host.as_str(), *port).to_socket_addrs()?.next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, format!("no address resolved for host {host}")));
+            }
+            ListenAddr::Interface { name, v6, port } => (name, *v6, *port),
+        };
+        let addrs = interface_addrs(name)?;
+        let addr = if v6 {
+            let is_link_local = |addr: &&SocketAddr| matches!(addr.ip(), IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80);
+            let mut v6_addrs = addrs.iter().filter(|addr| addr.is_ipv6());
+            v6_addrs.clone().find(|addr| !is_link_local(addr)).or_else(|| v6_addrs.next())
+        } else {
+            addrs.iter().find(|addr| addr.is_ipv4())
+        };
+        let mut addr = *addr.ok_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, format!("no {} address on interface {name}", if v6 { "IPv6" } else { "IPv4" })))?;
+        addr.set_port(port);
+        Ok(addr)
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(addr) = SocketAddr::from_str(s) {
+            return Ok(ListenAddr::Addr(addr));
+        }
+        let (target, interface) = match s.strip_prefix(INTERFACE_PREFIX) {
+            Some(target) => (target, true),
+            None => (s, false),
+        };
+        let (host, port) = target.rsplit_once(':').ok_or_else(|| format!("invalid listen addr {s}: missing port"))?;
+        let port = port.parse::<u16>().map_err(|err| format!("invalid listen addr {s}: {err}"))?;
+        if !interface {
+            if host.is_empty() || host.contains([':', '[', ']']) {
+                return Err(format!("invalid listen addr {s}"));
+            }
+            return Ok(ListenAddr::Host { host: host.to_string(), port });
+        }
+        let (name, v6) = match host.strip_prefix('[').and_then(|host| host.strip_suffix(']')) {
+            Some(name) => (name, true),
+            None => (host, false),
+        };
+        if name.is_empty() || name.contains([':', '[', ']']) {
+            return Err(format!("invalid listen addr {s}"));
+        }
+        Ok(ListenAddr::Interface { name: name.to_string(), v6, port })
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Addr(addr) => write!(f, "{addr}"),
+            ListenAddr::Host { host, port } => write!(f, "{host}:{port}"),
+            ListenAddr::Interface { name, v6: true, port } => write!(f, "{INTERFACE_PREFIX}[{name}]:{port}"),
+            ListenAddr::Interface { name, v6: false, port } => write!(f, "{INTERFACE_PREFIX}{name}:{port}"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        ListenAddr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_addr() {
+        assert_eq!(ListenAddr::from_str("0.0.0.0:5060").unwrap(), ListenAddr::Addr(SocketAddr::from_str("0.0.0.0:5060").unwrap()));
+        assert_eq!(ListenAddr::from_str("[::]:5060").unwrap().resolve().unwrap(), SocketAddr::from_str("[::]:5060").unwrap());
+        let host = ListenAddr::from_str("localhost:5060").unwrap();
+        assert_eq!(host, ListenAddr::Host { host: "localhost".to_string(), port: 5060 });
+        assert_eq!(host.to_string(), "localhost:5060");
+        assert!(host.resolve().unwrap().ip().is_loopback());
+        assert!(ListenAddr::from_str("lo").is_err());
+        assert!(ListenAddr::from_str("if:[lo:5060").is_err());
+        assert!(ListenAddr::from_str("[lo]:5060").is_err());
+        assert_eq!(ListenAddr::from_str("if:[eth0]:5060").unwrap().to_string(), "if:[eth0]:5060");
+        assert_eq!(ListenAddr::from_str("if:missing0:5060").unwrap().resolve().unwrap_err().kind(), io::ErrorKind::AddrNotAvailable);
+
+        //依赖回环网卡lo，环境中没有时跳过
+        if !interface_addrs("lo").is_ok_and(|addrs| addrs.iter().any(|addr| addr.ip() == Ipv4Addr::LOCALHOST)) {
+            return;
+        }
+        let lo = ListenAddr::from_str("if:lo:5060").unwrap();
+        assert_eq!(lo.to_string(), "if:lo:5060");
+        assert_eq!(lo.resolve().unwrap(), SocketAddr::from_str("127.0.0.1:5060").unwrap());
+    }
+}
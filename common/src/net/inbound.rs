@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{mpsc, Notify};
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::sync::mpsc::Sender;
+
+use exception::GlobalResult;
+use crate::net::ratelimit::{RateLimitConf, RateLimiter, Verdict};
+use crate::net::state::{Association, Overload, Zip};
+use crate::net::stats::Stats;
+
+/// 网络层向程序投递(input)的发送端，数据按ListenConf.overload处理通道满的情况
+/// 数据投递前按ListenConf.rate_limits限速；事件不受过载策略与限速影响，始终投递
+#[derive(Debug, Clone)]
+pub struct Inbound {
+    tx: Sender<Zip>,
+    overload: Overload,
+    backlog: Option<Backlog>,
+    limiter: Option<RateLimiter>,
+    stats: Stats,
+}
+
+//DropOldest：通道满时暂存于积压队列，由转发任务按序送入通道；积压数据满时丢弃最早的数据
+//数据与事件在积压中各自以capacity为上限，加上通道本身，input最多暂存capacity*3条
+#[derive(Debug, Clone)]
+struct Backlog {
+    queue: Arc<Mutex<Pending>>,
+    capacity: usize,
+    //唤醒转发任务；所有Inbound释放后转发任务送完积压后退出
+    wake: Sender<()>,
+    //转发任务取出积压后通知等待积压事件名额的发送方
+    space: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    items: VecDeque<Zip>,
+    //积压中的事件数
+    events: usize,
+}
+
+impl Pending {
+    fn pop(&mut self) -> Option<Zip> {
+        let zip = self.items.pop_front()?;
+        if matches!(zip, Zip::Event(_)) {
+            self.events -= 1;
+        }
+        Some(zip)
+    }
+}
+
+impl Inbound {
+    pub(crate) fn new(tx: Sender<Zip>, overload: Overload, capacity: usize, stats: Stats) -> Self {
+        let backlog = match overload {
+            Overload::DropOldest => Some(Backlog::spawn(tx.clone(), capacity)),
+            _ => None,
+        };
+        Self { tx, overload, backlog, limiter: None, stats }
+    }
+
+    //限速配置无效时返回错误，监听不启动
+    pub(crate) fn with_rate_limits(mut self, rate_limits: &[RateLimitConf]) -> GlobalResult<Self> {
+        self.limiter = RateLimiter::build(rate_limits)?;
+        Ok(self)
+    }
+
+    //投递事件，通道满时等待；DropOldest时积压事件达到capacity同样等待
+    pub async fn send(&self, zip: Zip) -> Result<(), SendError<Zip>> {
+        match &self.backlog {
+            None => self.tx.send(zip).await,
+            Some(backlog) => {
+                if self.tx.is_closed() {
+                    return Err(SendError(zip));
+                }
+                backlog.push_event(zip).await;
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// 限速检查，在数据构造为Zip投递前调用；Delay时等待令牌后返回Pass，超限计入统计
+    pub(crate) async fn throttle(&self, association: &Association) -> Verdict {
+        let Some(limiter) = &self.limiter else { return Verdict::Pass; };
+        match limiter.acquire(association) {
+            Verdict::Delay(wait) => {
+                self.stats.record_limited();
+                tokio::time::sleep(wait).await;
+                Verdict::Pass
+            }
+            Verdict::Pass => Verdict::Pass,
+            verdict => {
+                self.stats.record_limited();
+                verdict
+            }
+        }
+    }
+
+    /// 按过载策略投递数据；返回false表示通道已满且策略为Close，调用方应关闭连接
+    pub async fn deliver(&self, zip: Zip) -> bool {
+        let zip = match self.overload {
+            Overload::Block => {
+                let _ = self.tx.send(zip).await;
+                return true;
+            }
+            Overload::DropOldest => {
+                if let Some(backlog) = &self.backlog {
+                    if backlog.push_data(zip) {
+                        self.stats.record_dropped();
+                    }
+                }
+                return true;
+            }
+            Overload::DropNewest | Overload::Close => zip,
+        };
+        match self.tx.try_send(zip) {
+            Ok(()) | Err(TrySendError::Closed(_)) => true,
+            Err(TrySendError::Full(_)) => {
+                self.stats.record_dropped();
+                self.overload != Overload::Close
+            }
+        }
+    }
+}
+
+impl Backlog {
+    fn spawn(tx: Sender<Zip>, capacity: usize) -> Self {
+        let queue: Arc<Mutex<Pending>> = Arc::new(Mutex::new(Pending::default()));
+        let (wake, mut wake_rx) = mpsc::channel(1);
+        let space = Arc::new(Notify::new());
+        let relay_queue = queue.clone();
+        let relay_space = space.clone();
+        tokio::spawn(async move {
+            let pop = || {
+                let zip = relay_queue.lock().unwrap().pop();
+                relay_space.notify_waiters();
+                zip
+            };
+            'relay: while wake_rx.recv().await.is_some() {
+                loop {
+                    let Ok(permit) = tx.reserve().await else { break 'relay; };
+                    match pop() {
+                        None => break,
+                        Some(zip) => permit.send(zip),
+                    }
+                }
+            }
+            while let Some(zip) = pop() {
+                if tx.send(zip).await.is_err() {
+                    break;
+                }
+            }
+            //转发任务退出，唤醒等待积压事件名额的发送方
+            drop(wake_rx);
+            relay_space.notify_waiters();
+        });
+        Self { queue, capacity, wake, space }
+    }
+
+    //数据放入积压队列，积压数据满时丢弃最早的数据，返回是否发生丢弃
+    fn push_data(&self, zip: Zip) -> bool {
+        let mut dropped = false;
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.items.len() - queue.events >= self.capacity {
+                if let Some(index) = queue.items.iter().position(|zip| matches!(zip, Zip::Data(_))) {
+                    queue.items.remove(index);
+                    dropped = true;
+                }
+            }
+            queue.items.push_back(zip);
+        }
+        let _ = self.wake.try_send(());
+        dropped
+    }
+
+    //事件不丢弃，积压事件达到capacity时等待转发任务取出
+    async fn push_event(&self, zip: Zip) {
+        loop {
+            let space = self.space.notified();
+            //转发任务已退出(通道关闭)
+            if self.wake.is_closed() {
+                return;
+            }
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if queue.events < self.capacity {
+                    queue.events += 1;
+                    queue.items.push_back(zip);
+                    break;
+                }
+            }
+            let _ = self.wake.try_send(());
+            space.await;
+        }
+        let _ = self.wake.try_send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::time::timeout;
+
+    use crate::net::state::{Association, Event, EventKind, Package, Protocol};
+    use super::*;
+
+    fn data(association: &Association, i: u8) -> Zip {
+        Zip::build_data(Package::new(association.clone(), Bytes::from(vec![i])))
+    }
+
+    #[tokio::test]
+    async fn test_overload_policy() {
+        let addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let association = Association::new(addr, addr, Protocol::UDP);
+
+        let stats = Stats::new(addr, Protocol::UDP);
+        let (tx, mut rx) = mpsc::channel(1);
+        let inbound = Inbound::new(tx, Overload::DropNewest, 1, stats.clone());
+        assert!(inbound.deliver(data(&association, 0)).await);
+        assert!(inbound.deliver(data(&association, 1)).await);
+        assert_eq!(stats.dropped(), 1);
+        assert!(matches!(rx.recv().await, Some(Zip::Data(package)) if package.get_data()[0] == 0));
+
+        let (tx, _rx) = mpsc::channel(1);
+        let inbound = Inbound::new(tx, Overload::Close, 1, Stats::new(addr, Protocol::UDP));
+        assert!(inbound.deliver(data(&association, 0)).await);
+        assert!(!inbound.deliver(data(&association, 1)).await);
+
+        let stats = Stats::new(addr, Protocol::UDP);
+        let (tx, mut rx) = mpsc::channel(1);
+        let inbound = Inbound::new(tx, Overload::DropOldest, 2, stats.clone());
+        for i in 0..4 {
+            assert!(inbound.deliver(data(&association, i)).await);
+        }
+        inbound.send(Zip::build_event(Event::new(association.clone(), EventKind::PeerClosed))).await.unwrap();
+        drop(inbound);
+        let mut received = Vec::new();
+        while let Some(zip) = rx.recv().await {
+            match zip {
+                Zip::Data(package) => received.push(package.get_data()[0]),
+                Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::PeerClosed),
+            }
+        }
+        assert_eq!(*received.last().unwrap(), 3);
+        assert_eq!(received.len() as u64 + stats.dropped(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_event_backlog() {
+        let addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let association = Association::new(addr, addr, Protocol::UDP);
+        let event = || Zip::build_event(Event::new(association.clone(), EventKind::PeerClosed));
+        let wait = Duration::from_millis(200);
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let inbound = Inbound::new(tx, Overload::DropOldest, 1, Stats::new(addr, Protocol::UDP));
+        //通道与积压各容纳一个事件，第三个事件等待
+        timeout(wait, inbound.send(event())).await.unwrap().unwrap();
+        timeout(wait, inbound.send(event())).await.unwrap().unwrap();
+        assert!(timeout(wait, inbound.send(event())).await.is_err());
+        assert!(rx.recv().await.is_some());
+        timeout(wait, inbound.send(event())).await.unwrap().unwrap();
+
+        //通道关闭后不再等待
+        drop(rx);
+        timeout(wait, inbound.send(event())).await.unwrap().unwrap_err();
+    }
+}
@@ -0,0 +1,164 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use constructor::{Get, New};
+use log::error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use exception::{GlobalError, GlobalResult, TransError};
+use exception::code::net_err::NET_UNROUTABLE_ERROR_CODE;
+use crate::net::core;
+use crate::net::handle::NetHandle;
+use crate::net::state::{Association, ListenConf, Protocol, Zip, CHANNEL_BUFFER_SIZE};
+
+/// 单个监听的描述，codec等配置见ListenConf；ALL在同一地址监听TCP与UDP，地址不同时分别描述
+#[derive(Debug, Clone, New, Get)]
+pub struct ListenSpec {
+    protocol: Protocol,
+    addr: SocketAddr,
+    conf: ListenConf,
+}
+
+/// 多监听共用的输出发送端，按association的协议与本地地址路由到对应监听
+/// 没有对应监听时send直接返回错误，不进入任何输出通道
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    routes: Arc<Vec<(Protocol, SocketAddr, Sender<Zip>)>>,
+}
+
+impl Outbound {
+    pub async fn send(&self, zip: Zip) -> GlobalResult<()> {
+        let association = zip.get_association();
+        match self.route(&association) {
+            None => Err(GlobalError::new_biz_error(NET_UNROUTABLE_ERROR_CODE, &format!("no listener for {:?} on {}", association.get_protocol(), association.get_local_addr()), |msg| error!("{msg}"))),
+            Some(output_tx) => {
+                output_tx.send(zip).await.hand_log(|msg| error!("{msg}"))?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn is_routable(&self, association: &Association) -> bool {
+        self.route(association).is_some()
+    }
+
+    fn route(&self, association: &Association) -> Option<&Sender<Zip>> {
+        self.routes.iter()
+            .find(|(protocol, local_addr, _)| protocol == association.get_protocol() && local_addr == association.get_local_addr())
+            .map(|(_, _, output_tx)| output_tx)
+    }
+}
+
+//依次启动各监听，共用一个input通道(容量取各监听配置的最大值)；NetHandle按specs顺序返回
+//任一监听启动失败时关闭已启动的监听后返回错误
+pub async fn init(specs: Vec<ListenSpec>) -> GlobalResult<(Outbound, Receiver<Zip>, Vec<NetHandle>)> {
+    let capacity = specs.iter().map(|spec| spec.conf.channel_capacity()).max().unwrap_or(CHANNEL_BUFFER_SIZE);
+    let (input_tx, input_rx) = mpsc::channel(capacity);
+    let mut routes = Vec::new();
+    let mut handles: Vec<NetHandle> = Vec::with_capacity(specs.len());
+    for ListenSpec { protocol, addr, conf } in specs {
+        let (listen_tx, listen_rx) = oneshot::channel();
+        let (output_tx, handle) = match core::listen_into(protocol.clone(), addr, Arc::new(conf), input_tx.clone(), listen_tx).await {
+            Ok(listened) => listened,
+            Err(err) => {
+                for handle in &handles {
+                    handle.shutdown(false).await;
+                }
+                return Err(err);
+            }
+        };
+        let (accept_tx, accept_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+        let _ = core::accept(listen_rx, accept_tx).await.hand_log(|msg| error!("{msg}"));
+        tokio::spawn(async move {
+            core::rw(accept_rx).await;
+        });
+        match protocol {
+            //ALL的输出由classify按TCP/UDP分发
+            Protocol::ALL => {
+                routes.push((Protocol::TCP, addr, output_tx.clone()));
+                routes.push((Protocol::UDP, addr, output_tx));
+            }
+            protocol => routes.push((protocol, addr, output_tx)),
+        }
+        handles.push(handle);
+    }
+    Ok((Outbound { routes: Arc::new(routes) }, input_rx, handles))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpStream, UdpSocket};
+    use tokio::time;
+
+    use crate::net::state::Package;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_listeners() {
+        let tcp_addr = SocketAddr::from_str("127.0.0.1:38480").unwrap();
+        let udp_addr = SocketAddr::from_str("127.0.0.1:38481").unwrap();
+        let specs = vec![
+            ListenSpec::new(Protocol::TCP, tcp_addr, ListenConf::default()),
+            ListenSpec::new(Protocol::UDP, udp_addr, ListenConf::default()),
+        ];
+        let (outbound, mut rx, handles) = init(specs).await.unwrap();
+        assert_eq!(handles.len(), 2);
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.send_to(b"udp", udp_addr).await.unwrap();
+        let mut stream = TcpStream::connect(tcp_addr).await.unwrap();
+        stream.write_all(b"tcp").await.unwrap();
+        for _ in 0..3 {
+            match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+                Zip::Data(package) => {
+                    let reply = Bytes::from(format!("re:{}", String::from_utf8_lossy(package.get_data())));
+                    outbound.send(Zip::build_data(Package::new(package.get_association().clone(), reply))).await.unwrap();
+                }
+                Zip::Event(_) => {}
+            }
+        }
+        let mut buf = [0u8; 8];
+        let len = time::timeout(Duration::from_secs(5), peer.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"re:udp");
+        stream.read_exact(&mut buf[..6]).await.unwrap();
+        assert_eq!(&buf[..6], b"re:tcp");
+
+        let unroutable = Association::new(tcp_addr, peer.local_addr().unwrap(), Protocol::UDP);
+        assert!(!outbound.is_routable(&unroutable));
+        assert!(outbound.send(Zip::build_data(Package::new(unroutable, Bytes::from("x")))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_init_failure() {
+        let addr = SocketAddr::from_str("127.0.0.1:38482").unwrap();
+        let specs = vec![
+            ListenSpec::new(Protocol::TCP, addr, ListenConf::default()),
+            ListenSpec::new(Protocol::TCP, addr, ListenConf::default()),
+        ];
+        assert!(init(specs).await.is_err());
+        //已启动的监听已关闭，地址可再次监听
+        let (_outbound, _rx, handles) = init(vec![ListenSpec::new(Protocol::TCP, addr, ListenConf::default())]).await.unwrap();
+        assert!(handles[0].is_running());
+    }
+
+    #[tokio::test]
+    async fn test_all_unroutable() {
+        let addr = SocketAddr::from_str("127.0.0.1:38483").unwrap();
+        let (tx, mut rx, _handle) = crate::net::init_net(Protocol::ALL, addr, ListenConf::default()).await.unwrap();
+        let association = Association::new(addr, SocketAddr::from_str("127.0.0.1:5061").unwrap(), Protocol::TLS);
+        tx.send(Zip::build_data(Package::new(association.clone(), Bytes::from("x")))).await.unwrap();
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Event(event) => {
+                assert_eq!(event.get_association(), &association);
+                assert_eq!(event.get_kind(), &crate::net::state::EventKind::WriteError(std::io::ErrorKind::Unsupported));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
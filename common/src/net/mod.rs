@@ -1,18 +1,36 @@
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
 use log::error;
 
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::exception::{GlobalResult, TransError};
-use crate::net::state::{Zip};
+use crate::net::handle::NetHandle;
+use crate::net::state::{ListenConf, Zip};
 
 mod udp;
 mod tcp;
 mod core;
+mod unix;
 pub mod state;
+pub mod codec;
+pub mod tls;
+pub mod handle;
+pub mod registry;
+pub mod context;
+pub mod stats;
+pub mod inbound;
+pub mod admission;
+pub mod ratelimit;
+pub mod multicast;
+pub mod sockopt;
+pub mod iface;
+pub mod proxy;
+pub mod listeners;
+pub mod client;
 pub mod sdx;
 
-///todo 主动断开清理连接;创建事件句柄?封装数据枚举：EVENT-DATA
 // static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
 //     tokio::runtime::Builder::new_multi_thread()
 //         .thread_name_fn(|| {
@@ -24,14 +42,28 @@ pub mod sdx;
 //         .build()
 //         .hand_err(|msg| error!("net-pool Runtime build failed {msg}")).unwrap()
 // });
+//conf:监听配置，默认ListenConf::default()
+//返回的NetHandle用于关闭监听及其全部连接
 #[cfg(feature = "net")]
-pub async fn init_net(protocol: state::Protocol, socket_addr: SocketAddr) -> GlobalResult<(Sender<Zip>, Receiver<Zip>)> {
-    net_run(protocol, socket_addr).await
+pub async fn init_net(protocol: state::Protocol, socket_addr: SocketAddr, conf: ListenConf) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
+    net_run(protocol, socket_addr, conf).await
 }
 
-async fn net_run(protocol: state::Protocol, socket_addr: SocketAddr) -> GlobalResult<(Sender<Zip>, Receiver<Zip>)> {
+//多个监听(各自的协议、地址与codec)共用一个input通道，输出经Outbound按association路由
+#[cfg(feature = "net")]
+pub async fn init_listeners(specs: Vec<listeners::ListenSpec>) -> GlobalResult<(listeners::Outbound, Receiver<Zip>, Vec<NetHandle>)> {
+    listeners::init(specs).await
+}
+
+//Unix域socket监听，protocol为UNIX或UNIXGRAM
+#[cfg(feature = "net")]
+pub async fn init_unix(protocol: state::Protocol, path: impl AsRef<Path>, conf: ListenConf) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
+    unix::listen(protocol, path.as_ref(), conf).await
+}
+
+async fn net_run(protocol: state::Protocol, socket_addr: SocketAddr, conf: ListenConf) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
     let (listen_tx, listen_rx) = tokio::sync::oneshot::channel();
-    let rw = core::listen(protocol, socket_addr, listen_tx).await?;
+    let rw = core::listen(protocol, socket_addr, Arc::new(conf), listen_tx).await?;
     let (accept_tx, accept_rx) = tokio::sync::mpsc::channel(state::CHANNEL_BUFFER_SIZE);
     let _ = core::accept(listen_rx, accept_tx).await.hand_log(|msg| error!("{msg}"));
     tokio::spawn(async move {
@@ -0,0 +1,92 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use serde::Deserialize;
+use socket2::SockRef;
+
+use crate::net::state::ListenConf;
+
+/// UDP组播，配置于ListenConf.multicast；运行中可通过NetHandle::join_multicast/leave_multicast加入或退出
+/// # Examples
+///
+///  ```yaml
+/// multicast:
+///   groups:
+///     - group: 239.255.255.250
+///       interface: 192.168.1.10 #IPv4组的本地接口地址 可选 默认由系统选择
+///     - group: ff02::c
+///       index: 2 #IPv6组的接口索引 可选 默认0由系统选择
+///   ttl: 4 #组播TTL(IPv6为跳数) 可选
+///   loopback: false #本机是否接收自身发出的组播 可选
+///  ```
+/// IPv4组需监听IPv4地址，IPv6组需监听IPv6地址，通常监听0.0.0.0/::
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MulticastConf {
+    #[serde(default)]
+    groups: Vec<Membership>,
+    ttl: Option<u32>,
+    loopback: Option<bool>,
+}
+
+/// 组播组成员关系
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct Membership {
+    group: IpAddr,
+    interface: Option<Ipv4Addr>,
+    #[serde(default)]
+    index: u32,
+}
+
+impl MulticastConf {
+    pub fn new(groups: Vec<Membership>, ttl: Option<u32>, loopback: Option<bool>) -> Self {
+        Self { groups, ttl, loopback }
+    }
+}
+
+impl Membership {
+    pub fn v4(group: Ipv4Addr, interface: Option<Ipv4Addr>) -> Self {
+        Self { group: IpAddr::V4(group), interface, index: 0 }
+    }
+
+    pub fn v6(group: Ipv6Addr, index: u32) -> Self {
+        Self { group: IpAddr::V6(group), interface: None, index }
+    }
+
+    pub(crate) fn join(&self, socket: &SockRef) -> io::Result<()> {
+        match self.group {
+            IpAddr::V4(group) => socket.join_multicast_v4(&group, &self.interface.unwrap_or(Ipv4Addr::UNSPECIFIED)),
+            IpAddr::V6(group) => socket.join_multicast_v6(&group, self.index),
+        }
+    }
+
+    pub(crate) fn leave(&self, socket: &SockRef) -> io::Result<()> {
+        match self.group {
+            IpAddr::V4(group) => socket.leave_multicast_v4(&group, &self.interface.unwrap_or(Ipv4Addr::UNSPECIFIED)),
+            IpAddr::V6(group) => socket.leave_multicast_v6(&group, self.index),
+        }
+    }
+}
+
+//监听建立后设置广播与组播选项，并加入配置的组
+pub(crate) fn configure(socket: &SockRef, local_addr: &SocketAddr, conf: &ListenConf) -> io::Result<()> {
+    if *conf.get_broadcast() {
+        socket.set_broadcast(true)?;
+    }
+    let Some(multicast) = conf.get_multicast() else { return Ok(()); };
+    if let Some(ttl) = multicast.ttl {
+        match local_addr {
+            SocketAddr::V4(_) => socket.set_multicast_ttl_v4(ttl)?,
+            SocketAddr::V6(_) => socket.set_multicast_hops_v6(ttl)?,
+        }
+    }
+    if let Some(loopback) = multicast.loopback {
+        match local_addr {
+            SocketAddr::V4(_) => socket.set_multicast_loop_v4(loopback)?,
+            SocketAddr::V6(_) => socket.set_multicast_loop_v6(loopback)?,
+        }
+    }
+    for membership in &multicast.groups {
+        membership.join(socket)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,308 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use constructor::Get;
+use dashmap::DashMap;
+use log::error;
+use serde::{Deserialize, Deserializer};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use exception::{GlobalError, GlobalResult};
+use crate::net::admission::Cidr;
+
+//v2签名，其后为版本/命令、地址族/传输协议与2字节长度
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V2_FIXED_LEN: usize = 16;
+const V1_PREFIX: &[u8] = b"PROXY ";
+//v1头最大长度，含\r\n
+const V1_MAX_LEN: usize = 107;
+//未配置timeout时读取TCP PROXY头的超时
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+//UDP客户端到代理的映射空闲超时，超时未收到该客户端数据报时移除
+const PEER_IDLE_TTL: Duration = Duration::from_secs(300);
+//UDP映射的清理间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// PROXY protocol，配置于ListenConf.proxy_protocol；用于HAProxy、云负载均衡之后的监听
+/// TCP接受v1/v2，UDP仅v2(每个数据报携带)；解析出的客户端地址作为Association.remote_addr，接入控制与限速按客户端地址生效
+/// # Examples
+///
+///  ```yaml
+/// proxy_protocol:
+///   trusted: [10.0.0.0/8] #可信代理网段 必填且不可为空；仅可信对端要求并解析PROXY头，其余对端按直连处理
+///   timeout: 5 #TCP读取PROXY头超时(秒) 可选 默认5
+///  ```
+/// 可信对端缺少或携带无效的PROXY头时通知Rejected(InvalidProxy)：TCP连接关闭，UDP数据报丢弃
+/// 不信任任何对端的PROXY头，避免直连客户端伪造源地址绕过接入控制与限速
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyConf {
+    #[serde(deserialize_with = "non_empty_trusted")]
+    trusted: Vec<Cidr>,
+    timeout: Option<u64>,
+}
+
+impl ProxyConf {
+    //trusted为空时返回错误
+    pub fn new(trusted: Vec<Cidr>, timeout: Option<u64>) -> GlobalResult<Self> {
+        if trusted.is_empty() {
+            return Err(GlobalError::new_sys_error(EMPTY_TRUSTED, |msg| error!("{msg}")));
+        }
+        Ok(Self { trusted, timeout })
+    }
+
+    pub(crate) fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout.filter(|secs| *secs > 0).map(Duration::from_secs).unwrap_or(HEADER_TIMEOUT)
+    }
+}
+
+const EMPTY_TRUSTED: &str = "proxy_protocol.trusted must not be empty";
+
+fn non_empty_trusted<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Cidr>, D::Error> {
+    let trusted = Vec::<Cidr>::deserialize(deserializer)?;
+    if trusted.is_empty() {
+        return Err(serde::de::Error::custom(EMPTY_TRUSTED));
+    }
+    Ok(trusted)
+}
+
+/// 解析出的PROXY头；LOCAL命令(如代理健康检查)、UNKNOWN或非IP地址族时source/destination为None
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Get)]
+pub struct Header {
+    //头部长度(字节)，其后为原始数据
+    len: usize,
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>,
+}
+
+/// 按前缀识别v1/v2并解析；数据不完整时返回Ok(None)
+pub fn parse(buf: &[u8]) -> io::Result<Option<Header>> {
+    let prefix = buf.len().min(V1_PREFIX.len());
+    if buf[..prefix] == V1_PREFIX[..prefix] {
+        return parse_v1(buf);
+    }
+    let prefix = buf.len().min(V2_SIGNATURE.len());
+    if buf[..prefix] == V2_SIGNATURE[..prefix] {
+        return parse_v2(buf);
+    }
+    Err(invalid("missing proxy protocol header"))
+}
+
+/// v1文本格式：PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n
+pub fn parse_v1(buf: &[u8]) -> io::Result<Option<Header>> {
+    let Some(end) = buf.iter().position(|byte| *byte == b'\n') else {
+        return if buf.len() < V1_MAX_LEN { Ok(None) } else { Err(invalid("proxy v1 header too long")) };
+    };
+    if end + 1 > V1_MAX_LEN || end == 0 || buf[end - 1] != b'\r' {
+        return Err(invalid("invalid proxy v1 header"));
+    }
+    let line = std::str::from_utf8(&buf[..end - 1]).map_err(|_| invalid("invalid proxy v1 header"))?;
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(invalid("invalid proxy v1 header"));
+    }
+    let (source, destination) = match parts.next() {
+        Some("UNKNOWN") => (None, None),
+        Some(family @ ("TCP4" | "TCP6")) => {
+            let fields: Vec<&str> = parts.collect();
+            let [src, dst, sport, dport] = fields[..] else { return Err(invalid("invalid proxy v1 header")); };
+            let source = v1_addr(src, sport)?;
+            let destination = v1_addr(dst, dport)?;
+            if source.is_ipv4() != (family == "TCP4") || destination.is_ipv4() != (family == "TCP4") {
+                return Err(invalid("proxy v1 address family mismatch"));
+            }
+            (Some(source), Some(destination))
+        }
+        _ => return Err(invalid("unsupported proxy v1 protocol")),
+    };
+    Ok(Some(Header { len: end + 1, source, destination }))
+}
+
+fn v1_addr(ip: &str, port: &str) -> io::Result<SocketAddr> {
+    let ip = IpAddr::from_str(ip).map_err(|_| invalid("invalid proxy v1 address"))?;
+    let port = port.parse::<u16>().map_err(|_| invalid("invalid proxy v1 port"))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// v2二进制格式，TLV扩展忽略
+pub fn parse_v2(buf: &[u8]) -> io::Result<Option<Header>> {
+    if buf.len() < V2_FIXED_LEN {
+        return Ok(None);
+    }
+    if buf[..V2_SIGNATURE.len()] != V2_SIGNATURE || buf[12] >> 4 != 2 {
+        return Err(invalid("invalid proxy v2 header"));
+    }
+    let len = V2_FIXED_LEN + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < len {
+        return Ok(None);
+    }
+    //传输协议：0 UNSPEC，1 STREAM，2 DGRAM
+    if buf[13] & 0x0F > 2 {
+        return Err(invalid("invalid proxy v2 transport protocol"));
+    }
+    let addrs = &buf[V2_FIXED_LEN..len];
+    let (source, destination) = match (buf[12] & 0x0F, buf[13] >> 4) {
+        //LOCAL
+        (0, _) => (None, None),
+        (1, 1) if addrs.len() >= 12 => {
+            let ip = |offset: usize| IpAddr::V4(Ipv4Addr::new(addrs[offset], addrs[offset + 1], addrs[offset + 2], addrs[offset + 3]));
+            let port = |offset: usize| u16::from_be_bytes([addrs[offset], addrs[offset + 1]]);
+            (Some(SocketAddr::new(ip(0), port(8))), Some(SocketAddr::new(ip(4), port(10))))
+        }
+        (1, 2) if addrs.len() >= 36 => {
+            let ip = |offset: usize| IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[offset..offset + 16]).unwrap_or_default()));
+            let port = |offset: usize| u16::from_be_bytes([addrs[offset], addrs[offset + 1]]);
+            (Some(SocketAddr::new(ip(0), port(32))), Some(SocketAddr::new(ip(16), port(34))))
+        }
+        //UNSPEC、UNIX地址族
+        (1, 0 | 3) => (None, None),
+        _ => return Err(invalid("invalid proxy v2 command or address")),
+    };
+    Ok(Some(Header { len, source, destination }))
+}
+
+//逐段读取，不多读PROXY头之后的数据：v1逐字节读至\n，v2按长度字段读取
+pub(crate) async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Header> {
+    let mut buf = vec![0u8; V1_PREFIX.len()];
+    reader.read_exact(&mut buf).await?;
+    if buf == V1_PREFIX {
+        loop {
+            let byte = reader.read_u8().await?;
+            buf.push(byte);
+            if let Some(header) = parse_v1(&buf)? {
+                return Ok(header);
+            }
+        }
+    }
+    buf.resize(V2_FIXED_LEN, 0);
+    reader.read_exact(&mut buf[V1_PREFIX.len()..]).await?;
+    if buf[..V2_SIGNATURE.len()] != V2_SIGNATURE {
+        return Err(invalid("missing proxy protocol header"));
+    }
+    let len = V2_FIXED_LEN + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    buf.resize(len, 0);
+    reader.read_exact(&mut buf[V2_FIXED_LEN..]).await?;
+    parse_v2(&buf)?.ok_or_else(|| invalid("invalid proxy v2 header"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//UDP：客户端地址到(代理地址, 最近收到时间)的映射，回复经原代理发出；空闲超过PEER_IDLE_TTL的映射定期移除
+#[derive(Debug, Clone)]
+pub(crate) struct ProxyPeers {
+    table: Arc<DashMap<SocketAddr, (SocketAddr, Instant)>>,
+    next_prune: Arc<Mutex<Instant>>,
+}
+
+impl Default for ProxyPeers {
+    fn default() -> Self {
+        Self { table: Arc::new(DashMap::new()), next_prune: Arc::new(Mutex::new(Instant::now() + PRUNE_INTERVAL)) }
+    }
+}
+
+impl ProxyPeers {
+    pub(crate) fn record(&self, client_addr: SocketAddr, proxy_addr: SocketAddr) {
+        let now = Instant::now();
+        self.table.insert(client_addr, (proxy_addr, now));
+        self.prune(now);
+    }
+
+    //未经代理或映射已过期的对端直接发送
+    pub(crate) fn target(&self, remote_addr: &SocketAddr) -> SocketAddr {
+        self.table.get(remote_addr).map(|peer| peer.0).unwrap_or(*remote_addr)
+    }
+
+    fn prune(&self, now: Instant) {
+        {
+            let mut next_prune = self.next_prune.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if now < *next_prune {
+                return;
+            }
+            *next_prune = now + PRUNE_INTERVAL;
+        }
+        self.table.retain(|_, (_, last)| now.saturating_duration_since(*last) < PEER_IDLE_TTL);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.push(0x20 | command);
+        buf.push(family);
+        buf.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        buf.extend_from_slice(addrs);
+        buf
+    }
+
+    #[test]
+    fn test_parse() {
+        let header = parse(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET").unwrap().unwrap();
+        assert_eq!(header.get_len(), &47);
+        assert_eq!(header.get_source(), &Some(SocketAddr::from_str("192.168.0.1:56324").unwrap()));
+        assert_eq!(header.get_destination(), &Some(SocketAddr::from_str("192.168.0.11:443").unwrap()));
+        let header = parse(b"PROXY TCP6 ::1 ::2 5060 5061\r\n").unwrap().unwrap();
+        assert_eq!(header.get_source(), &Some(SocketAddr::from_str("[::1]:5060").unwrap()));
+        assert_eq!(parse(b"PROXY UNKNOWN\r\n").unwrap().unwrap().get_source(), &None);
+        assert_eq!(parse(b"PROXY TCP4 192.168.0.1").unwrap(), None);
+        assert_eq!(parse(b"PRO").unwrap(), None);
+        assert!(parse(b"PROXY TCP4 ::1 192.168.0.11 1 2\r\n").is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n").is_err());
+
+        let mut addrs = vec![10, 0, 0, 1, 10, 0, 0, 2];
+        addrs.extend_from_slice(&5060u16.to_be_bytes());
+        addrs.extend_from_slice(&5080u16.to_be_bytes());
+        //TLV扩展
+        addrs.extend_from_slice(&[0x04, 0x00, 0x01, 0xFF]);
+        let mut buf = v2(1, 0x12, &addrs);
+        let len = buf.len();
+        buf.extend_from_slice(b"INVITE");
+        let header = parse(&buf).unwrap().unwrap();
+        assert_eq!(header.get_len(), &len);
+        assert_eq!(header.get_source(), &Some(SocketAddr::from_str("10.0.0.1:5060").unwrap()));
+        assert_eq!(header.get_destination(), &Some(SocketAddr::from_str("10.0.0.2:5080").unwrap()));
+        assert_eq!(parse(&buf[..len - 1]).unwrap(), None);
+        assert_eq!(parse(&v2(0, 0x00, &[])).unwrap().unwrap().get_source(), &None);
+        assert!(parse(&v2(1, 0x11, &[10, 0, 0, 1])).is_err());
+        assert!(parse(&v2(1, 0x13, &addrs)).is_err());
+    }
+
+    #[test]
+    fn test_conf() {
+        assert!(ProxyConf::new(Vec::new(), None).is_err());
+        assert!(serde_yaml::from_str::<ProxyConf>("trusted: []").is_err());
+        assert!(serde_yaml::from_str::<ProxyConf>("timeout: 5").is_err());
+        let conf: ProxyConf = serde_yaml::from_str("trusted: [10.0.0.0/8]").unwrap();
+        assert!(conf.is_trusted(&IpAddr::from_str("10.1.2.3").unwrap()));
+        assert!(!conf.is_trusted(&IpAddr::from_str("192.168.0.1").unwrap()));
+    }
+
+    #[test]
+    fn test_peers_prune() {
+        let peers = ProxyPeers::default();
+        let client = SocketAddr::from_str("203.0.113.7:5060").unwrap();
+        let proxy_addr = SocketAddr::from_str("10.0.0.1:5060").unwrap();
+        peers.record(client, proxy_addr);
+        assert_eq!(peers.target(&client), proxy_addr);
+        let expired = Instant::now().checked_sub(PEER_IDLE_TTL).unwrap();
+        peers.table.insert(client, (proxy_addr, expired));
+        peers.prune(Instant::now() + PRUNE_INTERVAL);
+        assert_eq!(peers.len(), 0);
+        assert_eq!(peers.target(&client), client);
+    }
+}
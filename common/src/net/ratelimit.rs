@@ -0,0 +1,298 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use dashmap::mapref::one::RefMut;
+use log::error;
+use serde::{Deserialize, Deserializer};
+
+use exception::{GlobalError, GlobalResult};
+use crate::net::state::Association;
+
+//空闲桶清理间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 令牌桶限速，配置于ListenConf.rate_limits，可配置多条，按消息计数(UDP数据报/TCP经codec切分的帧)
+/// # Examples
+///
+///  ```yaml
+/// rate_limits:
+///   - rate: 20 #每秒令牌数
+///     burst: 40 #桶容量 可选 默认等于rate
+///     key: association #association|ip 按连接/对端或按对端IP限速 可选 默认association
+///     action: drop #drop|delay|disconnect 超限时丢弃/等待令牌/断开连接 可选 默认drop
+///  ```
+/// UDP无连接，disconnect按drop处理；delay会暂停整个socket的读取，预支的令牌不超过burst，超出后按drop处理
+/// 多条限速时所有桶均放行(或等待)才取令牌，被丢弃的消息不消耗任何桶
+/// rate须大于0，burst须不小于1，否则加载配置或启动监听时报错
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConf {
+    #[serde(deserialize_with = "positive_rate")]
+    rate: f64,
+    #[serde(default, deserialize_with = "valid_burst")]
+    burst: Option<f64>,
+    #[serde(default)]
+    key: LimitKey,
+    #[serde(default)]
+    action: LimitAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKey {
+    #[default]
+    Association,
+    Ip,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitAction {
+    #[default]
+    Drop,
+    Delay,
+    Disconnect,
+}
+
+//限速结果
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Verdict {
+    Pass,
+    Delay(Duration),
+    Drop,
+    Disconnect,
+}
+
+impl RateLimitConf {
+    pub fn new(rate: f64, burst: Option<f64>, key: LimitKey, action: LimitAction) -> Self {
+        Self { rate, burst, key, action }
+    }
+
+    pub(crate) fn key(&self) -> LimitKey {
+        self.key
+    }
+
+    fn burst(&self) -> f64 {
+        self.burst.unwrap_or(self.rate).max(1.0)
+    }
+
+    pub fn validate(&self) -> GlobalResult<()> {
+        check_rate(self.rate)
+            .and_then(|_| self.burst.map_or(Ok(()), check_burst))
+            .map_err(|msg| GlobalError::new_sys_error(&msg, |msg| error!("{msg}")))
+    }
+}
+
+fn check_rate(rate: f64) -> Result<(), String> {
+    if rate.is_finite() && rate > 0.0 { Ok(()) } else { Err(format!("invalid rate limit rate {rate}: must be greater than 0")) }
+}
+
+fn check_burst(burst: f64) -> Result<(), String> {
+    if burst.is_finite() && burst >= 1.0 { Ok(()) } else { Err(format!("invalid rate limit burst {burst}: must be at least 1")) }
+}
+
+fn positive_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let rate = f64::deserialize(deserializer)?;
+    check_rate(rate).map_err(serde::de::Error::custom)?;
+    Ok(rate)
+}
+
+fn valid_burst<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let burst = Option::<f64>::deserialize(deserializer)?;
+    if let Some(burst) = burst {
+        check_burst(burst).map_err(serde::de::Error::custom)?;
+    }
+    Ok(burst)
+}
+
+/// 监听内共享的限速器，按配置逐条取令牌
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    limits: Arc<Vec<Limit>>,
+}
+
+#[derive(Debug)]
+struct Limit {
+    conf: RateLimitConf,
+    buckets: DashMap<Key, Bucket>,
+    next_prune: Mutex<Instant>,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+enum Key {
+    Association(Association),
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    //未配置限速时返回None；rate/burst无效时返回错误
+    pub(crate) fn build(confs: &[RateLimitConf]) -> GlobalResult<Option<Self>> {
+        if confs.is_empty() {
+            return Ok(None);
+        }
+        let mut limits = Vec::with_capacity(confs.len());
+        for conf in confs {
+            conf.validate()?;
+            limits.push(Limit { conf: conf.clone(), buckets: DashMap::new(), next_prune: Mutex::new(Instant::now() + PRUNE_INTERVAL) });
+        }
+        Ok(Some(Self { limits: Arc::new(limits) }))
+    }
+
+    //多条限速取最严格的结果：Disconnect > Drop > Delay(取最长等待)
+    pub(crate) fn acquire(&self, association: &Association) -> Verdict {
+        self.acquire_at(association, Instant::now())
+    }
+
+    //先检查所有桶再取令牌：按配置顺序持有各桶，结果为Pass或Delay时每个桶各取一个令牌，否则均不消耗
+    fn acquire_at(&self, association: &Association, now: Instant) -> Verdict {
+        for limit in self.limits.iter() {
+            limit.prune(now);
+        }
+        let mut buckets: Vec<RefMut<'_, Key, Bucket>> = self.limits.iter().map(|limit| limit.bucket(association, now)).collect();
+        let verdict = self.limits.iter().zip(buckets.iter())
+            .map(|(limit, bucket)| limit.check(bucket))
+            .fold(Verdict::Pass, |verdict, next| match (verdict, next) {
+                (Verdict::Disconnect, _) | (_, Verdict::Disconnect) => Verdict::Disconnect,
+                (Verdict::Drop, _) | (_, Verdict::Drop) => Verdict::Drop,
+                (Verdict::Delay(a), Verdict::Delay(b)) => Verdict::Delay(a.max(b)),
+                (Verdict::Delay(wait), Verdict::Pass) | (Verdict::Pass, Verdict::Delay(wait)) => Verdict::Delay(wait),
+                (Verdict::Pass, Verdict::Pass) => Verdict::Pass,
+            });
+        if matches!(verdict, Verdict::Pass | Verdict::Delay(_)) {
+            for bucket in buckets.iter_mut() {
+                bucket.tokens -= 1.0;
+            }
+        }
+        verdict
+    }
+}
+
+impl Limit {
+    //取对端的桶并按流逝时间补充令牌
+    fn bucket(&self, association: &Association, now: Instant) -> RefMut<'_, Key, Bucket> {
+        let key = match self.conf.key {
+            LimitKey::Association => Key::Association(association.clone()),
+            LimitKey::Ip => Key::Ip(association.get_remote_addr().ip()),
+        };
+        let burst = self.conf.burst();
+        let mut bucket = self.buckets.entry(key).or_insert_with(|| Bucket { tokens: burst, last: now });
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.conf.rate).min(burst);
+        bucket.last = now;
+        bucket
+    }
+
+    //检查取一个令牌的结果，不修改桶
+    fn check(&self, bucket: &Bucket) -> Verdict {
+        if bucket.tokens >= 1.0 {
+            return Verdict::Pass;
+        }
+        match self.conf.action {
+            LimitAction::Drop => Verdict::Drop,
+            LimitAction::Disconnect => Verdict::Disconnect,
+            //预支令牌，等待补足后放行；预支超过burst时丢弃，避免等待无限延长
+            LimitAction::Delay if bucket.tokens - 1.0 >= -self.conf.burst() => {
+                Verdict::Delay(Duration::from_secs_f64((1.0 - bucket.tokens) / self.conf.rate))
+            }
+            LimitAction::Delay => Verdict::Drop,
+        }
+    }
+
+    //移除已补满的空闲桶，避免UDP对端增多时无限增长
+    fn prune(&self, now: Instant) {
+        {
+            let mut next_prune = self.next_prune.lock().unwrap();
+            if now < *next_prune {
+                return;
+            }
+            *next_prune = now + PRUNE_INTERVAL;
+        }
+        let burst = self.conf.burst();
+        let rate = self.conf.rate;
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + now.saturating_duration_since(bucket.last).as_secs_f64() * rate < burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use crate::net::state::Protocol;
+    use super::*;
+
+    fn association(remote: &str) -> Association {
+        Association::new(SocketAddr::from_str("127.0.0.1:5060").unwrap(), SocketAddr::from_str(remote).unwrap(), Protocol::UDP)
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let limiter = RateLimiter::build(&[RateLimitConf::new(10.0, Some(2.0), LimitKey::Association, LimitAction::Drop)]).unwrap().unwrap();
+        let a = association("10.0.0.1:5060");
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Drop);
+        assert_eq!(limiter.acquire_at(&association("10.0.0.1:5061"), now), Verdict::Pass);
+        let later = now + Duration::from_millis(100);
+        assert_eq!(limiter.acquire_at(&a, later), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&a, later), Verdict::Drop);
+
+        let limiter = RateLimiter::build(&[
+            RateLimitConf::new(10.0, Some(1.0), LimitKey::Ip, LimitAction::Delay),
+            RateLimitConf::new(100.0, Some(1.0), LimitKey::Association, LimitAction::Disconnect),
+        ]).unwrap().unwrap();
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&association("10.0.0.1:5061"), now), Verdict::Delay(Duration::from_millis(100)));
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Disconnect);
+        assert!(RateLimiter::build(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_no_charge_on_reject() {
+        let now = Instant::now();
+        let a = association("10.0.0.1:5060");
+        //按IP的桶拒绝时，按association的桶不消耗令牌
+        let limiter = RateLimiter::build(&[
+            RateLimitConf::new(10.0, Some(2.0), LimitKey::Association, LimitAction::Drop),
+            RateLimitConf::new(10.0, Some(1.0), LimitKey::Ip, LimitAction::Drop),
+        ]).unwrap().unwrap();
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Pass);
+        for _ in 0..5 {
+            assert_eq!(limiter.acquire_at(&a, now), Verdict::Drop);
+        }
+        let later = now + Duration::from_millis(100);
+        assert_eq!(limiter.acquire_at(&a, later), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&association("10.0.0.2:5060"), later), Verdict::Pass);
+
+        //delay预支至多burst个令牌，超出后丢弃且不再延长等待
+        let limiter = RateLimiter::build(&[RateLimitConf::new(10.0, Some(2.0), LimitKey::Association, LimitAction::Delay)]).unwrap().unwrap();
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Pass);
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Delay(Duration::from_millis(100)));
+        assert_eq!(limiter.acquire_at(&a, now), Verdict::Delay(Duration::from_millis(200)));
+        for _ in 0..5 {
+            assert_eq!(limiter.acquire_at(&a, now), Verdict::Drop);
+        }
+        assert_eq!(limiter.acquire_at(&a, now + Duration::from_millis(100)), Verdict::Delay(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_invalid_conf() {
+        for (rate, burst) in [(0.0, None), (-1.0, None), (f64::NAN, None), (f64::INFINITY, None), (10.0, Some(0.5)), (10.0, Some(f64::NAN))] {
+            assert!(RateLimiter::build(&[RateLimitConf::new(rate, burst, LimitKey::Association, LimitAction::Delay)]).is_err());
+        }
+        assert!(serde_yaml::from_str::<RateLimitConf>("rate: 0").is_err());
+        assert!(serde_yaml::from_str::<RateLimitConf>("rate: 10\nburst: 0").is_err());
+        assert!(serde_yaml::from_str::<RateLimitConf>("rate: 10\nburst: 20").is_ok());
+    }
+}
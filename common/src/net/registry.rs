@@ -0,0 +1,202 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc::Sender;
+
+use crate::net::context::Context;
+use crate::net::state::{Association, Event, EventKind, Zip};
+
+/// 监听的TCP/TLS连接登记表，每个监听独立持有，由NetHandle::registry获取
+/// 登记各连接的输出句柄与上下文，连接断开时移除
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    handles: Arc<DashMap<Association, Entry>>,
+    //各对端IP的连接数
+    per_ip: Arc<DashMap<IpAddr, usize>>,
+    //已接入但尚未登记(TLS握手、读取PROXY头中)的预留名额
+    reserved: Arc<Reserved>,
+}
+
+#[derive(Debug, Default)]
+struct Reserved {
+    total: AtomicUsize,
+    per_ip: DashMap<IpAddr, usize>,
+}
+
+/// 接入名额预留，计入接入控制的连接数；连接登记后或握手、PROXY头读取失败时随drop释放
+#[derive(Debug)]
+pub(crate) struct Slot {
+    reserved: Arc<Reserved>,
+    ip: Option<IpAddr>,
+}
+
+impl Slot {
+    //预留对端IP名额，返回预留后该IP的预留数
+    pub(crate) fn bind_ip(&mut self, ip: IpAddr) -> usize {
+        if let Some(old) = self.ip.replace(ip) {
+            release_ip(&self.reserved, &old);
+        }
+        let mut count = self.reserved.per_ip.entry(ip).or_default();
+        *count += 1;
+        *count
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.reserved.total.fetch_sub(1, Ordering::AcqRel);
+        if let Some(ip) = self.ip.take() {
+            release_ip(&self.reserved, &ip);
+        }
+    }
+}
+
+fn release_ip(reserved: &Reserved, ip: &IpAddr) {
+    reserved.per_ip.remove_if_mut(ip, |_, count| {
+        *count -= 1;
+        *count == 0
+    });
+}
+
+#[derive(Debug)]
+struct Entry {
+    lone_output_tx: Sender<Zip>,
+    context: Context,
+}
+
+impl Registry {
+    pub(crate) fn insert(&self, association: Association, lone_output_tx: Sender<Zip>) {
+        let ip = association.get_remote_addr().ip();
+        if self.handles.insert(association, Entry { lone_output_tx, context: Context::default() }).is_none() {
+            *self.per_ip.entry(ip).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn remove(&self, association: &Association) -> Option<Sender<Zip>> {
+        let (_, entry) = self.handles.remove(association)?;
+        self.per_ip.remove_if_mut(&association.get_remote_addr().ip(), |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+        entry.context.clear();
+        Some(entry.lone_output_tx)
+    }
+
+    pub(crate) fn get(&self, association: &Association) -> Option<Sender<Zip>> {
+        self.handles.get(association).map(|entry| entry.lone_output_tx.clone())
+    }
+
+    pub(crate) fn entries(&self) -> Vec<(Association, Sender<Zip>)> {
+        self.handles.iter().map(|entry| (entry.key().clone(), entry.value().lone_output_tx.clone())).collect()
+    }
+
+    /// 存活连接的上下文，连接不存在时返回None
+    pub fn context(&self, association: &Association) -> Option<Context> {
+        self.handles.get(association).map(|entry| entry.context.clone())
+    }
+
+    //存活连接，含正在主动连接中的
+    pub fn associations(&self) -> Vec<Association> {
+        self.handles.iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    //预留一个接入名额，返回预留后的预留总数
+    pub(crate) fn reserve(&self) -> (Slot, usize) {
+        let total = self.reserved.total.fetch_add(1, Ordering::AcqRel) + 1;
+        (Slot { reserved: self.reserved.clone(), ip: None }, total)
+    }
+
+    /// 已接入但尚未登记(TLS握手、读取PROXY头中)的连接数
+    pub fn reserved(&self) -> usize {
+        self.reserved.total.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn count_by_ip(&self, ip: &IpAddr) -> usize {
+        self.per_ip.get(ip).map(|count| *count).unwrap_or(0)
+    }
+
+    pub fn contains(&self, association: &Association) -> bool {
+        self.handles.contains_key(association)
+    }
+
+    //按对端地址查找连接
+    pub fn find_by_remote(&self, remote_addr: &SocketAddr) -> Option<Association> {
+        self.handles.iter()
+            .find(|entry| entry.key().get_remote_addr() == remote_addr)
+            .map(|entry| entry.key().clone())
+    }
+
+    /// 强制关闭连接，排在已提交的输出数据之后执行，关闭后通知LocallyClosed
+    /// 连接不存在时返回false
+    pub async fn close(&self, association: &Association) -> bool {
+        self.command(association, EventKind::LocallyClosed).await
+    }
+
+    /// 复位连接(RST)，已提交但未写出的数据丢弃，完成后通知Aborted
+    pub async fn abort(&self, association: &Association) -> bool {
+        self.command(association, EventKind::Aborted).await
+    }
+
+    /// 写出已提交的数据后关闭写方向，完成后通知HalfClosed，连接仍可读取
+    pub async fn half_close(&self, association: &Association) -> bool {
+        self.command(association, EventKind::HalfClosed).await
+    }
+
+    async fn command(&self, association: &Association, kind: EventKind) -> bool {
+        match self.get(association) {
+            None => false,
+            Some(lone_output_tx) => {
+                lone_output_tx.send(Zip::build_event(Event::new(association.clone(), kind))).await.is_ok()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tokio::sync::mpsc;
+
+    use crate::net::state::Protocol;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registry() {
+        let registry = Registry::default();
+        let local_addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let remote_addr = SocketAddr::from_str("10.0.0.1:5060").unwrap();
+        let association = Association::new(local_addr, remote_addr, Protocol::TCP);
+        let (tx, mut rx) = mpsc::channel(1);
+        registry.insert(association.clone(), tx);
+
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.associations(), vec![association.clone()]);
+        assert_eq!(registry.find_by_remote(&remote_addr), Some(association.clone()));
+        assert_eq!(registry.find_by_remote(&local_addr), None);
+        assert!(registry.close(&association).await);
+        match rx.recv().await.unwrap() {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::LocallyClosed),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let context = registry.context(&association).unwrap();
+        context.insert(String::from("device"));
+        assert_eq!(registry.context(&association).unwrap().get::<String>().unwrap().as_str(), "device");
+        assert!(registry.remove(&association).is_some());
+        assert!(registry.is_empty());
+        assert!(context.is_empty());
+        assert!(registry.context(&association).is_none());
+        assert!(!registry.close(&association).await);
+    }
+}
@@ -1,11 +1,19 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::net::{TcpListener, UdpSocket};
 use log::error;
+use tokio::runtime::Handle;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{Receiver, Sender};
 use exception::{GlobalError, GlobalResult, TransError};
-use crate::net::state::{CHANNEL_BUFFER_SIZE, Gate, GateListener, Protocol, Zip};
+use crate::net::state::{CHANNEL_BUFFER_SIZE, Gate, ListenConf, GateListener, Protocol, Zip};
 use crate::net::{tcp, udp};
+use crate::net::inbound::Inbound;
+use crate::net::handle::NetHandle;
+use crate::net::sockopt::SocketOptions;
+use crate::net::handle::Signal;
+use crate::net::udp::Routes;
+use crate::net::proxy::ProxyPeers;
 
 /*
 使用std创建网络句柄：解决跨运行时、io、网络驱动绑定问题
@@ -22,6 +30,12 @@ pub fn listen(protocol: Protocol, socket_addr: SocketAddr) -> GlobalResult<(Opti
             let tcp_listener = TcpListener::bind(socket_addr).hand_log(|msg| error!("{msg}"))?;
             Ok((Some(tcp_listener), None))
         }
+        Protocol::TLS => {
+            Err(GlobalError::new_sys_error("TLS listener is not supported by sdx, use init_net instead", |msg| error!("{msg}")))
+        }
+        Protocol::UNIX | Protocol::UNIXGRAM => {
+            Err(GlobalError::new_sys_error("UNIX listener is not supported by sdx, use init_unix instead", |msg| error!("{msg}")))
+        }
         Protocol::ALL => {
             let udp_socket = UdpSocket::bind(socket_addr).hand_log(|msg| error!("{msg}"))?;
             let tcp_listener = TcpListener::bind(socket_addr).hand_log(|msg| error!("{msg}"))?;
@@ -30,35 +44,80 @@ pub fn listen(protocol: Protocol, socket_addr: SocketAddr) -> GlobalResult<(Opti
     }
 }
 
+/// 按socket选项创建std监听，如SO_REUSEPORT使多个进程/运行时监听同一端口；accept的连接选项需同时配置于ListenConf.socket
+#[cfg(feature = "net")]
+pub fn listen_with(protocol: Protocol, socket_addr: SocketAddr, options: &SocketOptions) -> GlobalResult<(Option<TcpListener>, Option<UdpSocket>)> {
+    match protocol {
+        Protocol::UDP => {
+            let udp_socket = options.bind_udp(socket_addr).hand_log(|msg| error!("{msg}"))?;
+            Ok((None, Some(udp_socket)))
+        }
+        Protocol::TCP => {
+            let tcp_listener = options.bind_tcp(socket_addr).hand_log(|msg| error!("{msg}"))?;
+            Ok((Some(tcp_listener), None))
+        }
+        Protocol::TLS => {
+            Err(GlobalError::new_sys_error("TLS listener is not supported by sdx, use init_net instead", |msg| error!("{msg}")))
+        }
+        Protocol::UNIX | Protocol::UNIXGRAM => {
+            Err(GlobalError::new_sys_error("UNIX listener is not supported by sdx, use init_unix instead", |msg| error!("{msg}")))
+        }
+        Protocol::ALL => {
+            let udp_socket = options.bind_udp(socket_addr).hand_log(|msg| error!("{msg}"))?;
+            let tcp_listener = options.bind_tcp(socket_addr).hand_log(|msg| error!("{msg}"))?;
+            Ok((Some(tcp_listener), Some(udp_socket)))
+        }
+    }
+}
+
 #[cfg(feature = "net")]
-pub async fn run_by_tokio(tu: (Option<TcpListener>, Option<UdpSocket>)) -> GlobalResult<(Sender<Zip>, Receiver<Zip>)> {
+pub async fn run_by_tokio(tu: (Option<TcpListener>, Option<UdpSocket>), conf: ListenConf) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
+    let conf = Arc::new(conf);
+    let (local_addr, protocol) = match &tu {
+        (Some(tl), None) => (tl.local_addr().hand_log(|msg| error!("{msg}"))?, Protocol::TCP),
+        (None, Some(us)) => (us.local_addr().hand_log(|msg| error!("{msg}"))?, Protocol::UDP),
+        (Some(tl), Some(_)) => (tl.local_addr().hand_log(|msg| error!("{msg}"))?, Protocol::ALL),
+        (None, None) => panic!("At least one network listener is required"),
+    };
+    let (handle, signal) = NetHandle::new(local_addr, protocol);
     let (listen_tx, listen_rx) = tokio::sync::oneshot::channel();
+    let capacity = conf.channel_capacity();
     //socket 读数据通道 input
-    let (input_tx, input_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+    let (input_tx, input_rx) = mpsc::channel(capacity);
     //socket 写数据通道 output
-    let (output_tx, output_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
+    let (output_tx, output_rx) = mpsc::channel(capacity);
+    handle.stats().watch_channels(input_tx.downgrade(), output_tx.downgrade());
+    let input_tx = Inbound::new(input_tx, *conf.get_overload(), capacity, handle.stats().clone())
+        .with_rate_limits(conf.get_rate_limits())?;
     match tu {
         (Some(tl), None) => {
-            let gate = Gate::new(tl.local_addr().hand_log(|msg| error!("{msg}"))?, input_tx, output_rx);
+            let gate = Gate::new(local_addr, input_tx, output_rx, conf, signal, handle.registry().clone(), None);
             let listener = tcp::listen_by_std(gate, tl)?;
             listen_tx.send(listener).map_err(|_err| GlobalError::new_sys_error("net io listen err:channel has drop", |msg| error!("{msg}")))?;
         }
         (None, Some(us)) => {
-            let gate = Gate::new(us.local_addr().hand_log(|msg| error!("{msg}"))?, input_tx, output_rx);
+            let gate = Gate::new(local_addr, input_tx, output_rx, conf, signal, handle.registry().clone(), None);
             let listener = udp::listen_by_std(gate, us)?;
+            if let Some(udp_socket) = listener.udp_socket() {
+                handle.attach_udp(udp_socket)?;
+            }
             listen_tx.send(listener).map_err(|_err| GlobalError::new_sys_error("net io listen err:channel has drop", |msg| error!("{msg}")))?;
         }
         (Some(tl), Some(us)) => {
-            let (tw_tx, tw_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-            let tcp_gate = Gate::new(tl.local_addr().hand_log(|msg| error!("{msg}"))?, input_tx.clone(), tw_rx);
+            let (tw_tx, tw_rx) = mpsc::channel(capacity);
+            let tcp_gate = Gate::new(local_addr, input_tx.clone(), tw_rx, conf.clone(), signal.clone(), handle.registry().clone(), None);
             let tcp_listener = tcp::listen_by_std(tcp_gate, tl)?;
-            let (uw_tx, uw_rx) = mpsc::channel(CHANNEL_BUFFER_SIZE);
-            let udp_gate = Gate::new(us.local_addr().hand_log(|msg| error!("{msg}"))?, input_tx.clone(), uw_rx);
+            let (uw_tx, uw_rx) = mpsc::channel(capacity);
+            let udp_gate = Gate::new(us.local_addr().hand_log(|msg| error!("{msg}"))?, input_tx.clone(), uw_rx, conf, signal.clone(), handle.registry().clone(), None);
+            let classify_input = input_tx.clone();
             let udp_listener = udp::listen_by_std(udp_gate, us)?;
+            if let Some(udp_socket) = udp_listener.udp_socket() {
+                handle.attach_udp(udp_socket)?;
+            }
 
             let gate_listener = GateListener::build_all(tcp_listener, udp_listener);
             listen_tx.send(gate_listener).map_err(|_err| GlobalError::new_sys_error("net io listen err:channel has drop", |msg| error!("{msg}")))?;
-            crate::net::core::classify(output_rx, tw_tx, uw_tx);
+            crate::net::core::classify(output_rx, tw_tx, uw_tx, classify_input, signal);
         }
         (None, None) => {
             panic!("At least one network listener is required")
@@ -69,5 +128,145 @@ pub async fn run_by_tokio(tu: (Option<TcpListener>, Option<UdpSocket>)) -> Globa
     tokio::spawn(async move {
         crate::net::core::rw(accept_rx).await;
     });
-    Ok((output_tx, input_rx))
-}
\ No newline at end of file
+    Ok((output_tx, input_rx, handle))
+}
+
+/// 为同一地址创建shards个SO_REUSEPORT的UDP socket，由run_sharded分布到多个运行时读写
+#[cfg(feature = "net")]
+pub fn listen_sharded(socket_addr: SocketAddr, shards: usize, options: &SocketOptions) -> GlobalResult<Vec<UdpSocket>> {
+    let options = SocketOptions { reuse_port: Some(true), ..options.clone() };
+    let mut sockets = Vec::with_capacity(shards);
+    for _ in 0..shards.max(1) {
+        sockets.push(options.bind_udp(socket_addr).hand_log(|msg| error!("{msg}"))?);
+    }
+    Ok(sockets)
+}
+
+/// 各socket依次在runtimes上(数量不足时轮转，为空时使用当前运行时)独立读写，输入合并到同一Receiver
+/// 输出按对端最近一次接收数据的socket发出，未收到过数据的对端按地址散列选择
+#[cfg(feature = "net")]
+pub async fn run_sharded(sockets: Vec<UdpSocket>, runtimes: Vec<Handle>, conf: ListenConf) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
+    let conf = Arc::new(conf);
+    let Some(first) = sockets.first() else {
+        return Err(GlobalError::new_sys_error("At least one udp socket is required", |msg| error!("{msg}")));
+    };
+    let local_addr = first.local_addr().hand_log(|msg| error!("{msg}"))?;
+    let (handle, signal) = NetHandle::new(local_addr, Protocol::UDP);
+    let capacity = conf.channel_capacity();
+    //socket 读数据通道 input
+    let (input_tx, input_rx) = mpsc::channel(capacity);
+    //socket 写数据通道 output
+    let (output_tx, output_rx) = mpsc::channel(capacity);
+    handle.stats().watch_channels(input_tx.downgrade(), output_tx.downgrade());
+    let input_tx = Inbound::new(input_tx, *conf.get_overload(), capacity, handle.stats().clone())
+        .with_rate_limits(conf.get_rate_limits())?;
+    let routes = Routes::new(sockets.len());
+    let proxied = conf.get_proxy_protocol().as_ref().map(|_| ProxyPeers::default());
+    let current = Handle::current();
+    let mut shard_txs = Vec::with_capacity(sockets.len());
+    for (shard, std_udp_socket) in sockets.into_iter().enumerate() {
+        let runtime = runtimes.get(shard % runtimes.len().max(1)).cloned().unwrap_or_else(|| current.clone());
+        std_udp_socket.set_nonblocking(true).hand_log(|msg| error!("{msg}"))?;
+        //在目标运行时注册socket，读写由该运行时的io驱动完成
+        let udp_socket = {
+            let _guard = runtime.enter();
+            tokio::net::UdpSocket::from_std(std_udp_socket).hand_log(|msg| error!("{msg}"))?
+        };
+        udp::configure(&udp_socket, &local_addr, &conf).hand_log(|msg| error!("{msg}"))?;
+        //运行中的组播调整作用于每个分片
+        handle.attach_udp(&udp_socket)?;
+        let (shard_tx, shard_rx) = mpsc::channel(capacity);
+        shard_txs.push(shard_tx);
+        let read_socket = Arc::new(udp_socket);
+        let write_socket = read_socket.clone();
+        let (read_input, write_input) = (input_tx.clone(), input_tx.clone());
+        let (read_signal, write_signal) = (signal.clone(), signal.clone());
+        let (read_conf, route) = (conf.clone(), (routes.clone(), shard));
+        let (read_proxied, write_proxied) = (proxied.clone(), proxied.clone());
+        runtime.spawn(async move {
+            udp::read(local_addr, &read_socket, read_input, read_signal, read_conf, Some(route), read_proxied).await;
+        });
+        runtime.spawn(async move {
+            udp::write(&write_socket, shard_rx, write_input, write_signal, write_proxied).await;
+        });
+    }
+    dispatch(output_rx, shard_txs, routes, signal);
+    Ok((output_tx, input_rx, handle))
+}
+
+//按路由表将输出分发到各分片的写任务
+#[cfg(feature = "net")]
+fn dispatch(mut output: Receiver<Zip>, shard_txs: Vec<Sender<Zip>>, routes: Routes, mut signal: Signal) {
+    tokio::spawn(async move {
+        while let Some(zip) = crate::net::core::recv_output(&mut output, &mut signal).await {
+            let shard = routes.lookup(zip.get_association().get_remote_addr());
+            let _ = shard_txs[shard].send(zip).await.hand_log(|msg| error!("{msg}"));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use tokio::runtime::{Builder, Runtime};
+    use tokio::time;
+
+    use super::*;
+
+    #[test]
+    fn test_sharded_udp() {
+        let shard_runtimes: Vec<Runtime> = (0..2).map(|_| Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap()).collect();
+        let runtimes = shard_runtimes.iter().map(|runtime| runtime.handle().clone()).collect();
+        let main = Builder::new_current_thread().enable_all().build().unwrap();
+        main.block_on(async move {
+            let local_addr = SocketAddr::from_str("127.0.0.1:38471").unwrap();
+            let sockets = listen_sharded(local_addr, 2, &SocketOptions::default()).unwrap();
+            let (tx, mut rx, handle) = run_sharded(sockets, runtimes, ListenConf::default()).await.unwrap();
+            assert_eq!(handle.udp_sockets(), 2);
+            let mut peers = Vec::new();
+            for i in 0..8u8 {
+                let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                peer.send_to(&[i], local_addr).await.unwrap();
+                peers.push(peer);
+            }
+            for _ in 0..8 {
+                match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+                    Zip::Data(package) => tx.send(Zip::build_data(package)).await.unwrap(),
+                    other => panic!("unexpected {other:?}"),
+                }
+            }
+            for (i, peer) in peers.iter().enumerate() {
+                let mut buf = [0u8; 8];
+                let (len, from) = time::timeout(Duration::from_secs(5), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+                assert_eq!((&buf[..len], from), (&[i as u8][..], local_addr));
+            }
+
+            //分片绑定不同端口，回复的源端口即发出回复的分片，须与接收请求的分片一致
+            let shard_addrs = [SocketAddr::from_str("127.0.0.1:38472").unwrap(), SocketAddr::from_str("127.0.0.1:38473").unwrap()];
+            let sockets = shard_addrs.iter().map(|addr| UdpSocket::bind(addr).unwrap()).collect();
+            let (tx, mut rx, _handle) = run_sharded(sockets, vec![], ListenConf::default()).await.unwrap();
+            let mut peers = Vec::new();
+            for i in 0..8u8 {
+                let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let shard_addr = shard_addrs[i as usize % 2];
+                peer.send_to(&[i], shard_addr).await.unwrap();
+                //逐个回复，确保路由已记录
+                match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+                    Zip::Data(package) => tx.send(Zip::build_data(package)).await.unwrap(),
+                    other => panic!("unexpected {other:?}"),
+                }
+                peers.push((peer, shard_addr));
+            }
+            for (i, (peer, shard_addr)) in peers.iter().enumerate() {
+                let mut buf = [0u8; 8];
+                let (len, from) = time::timeout(Duration::from_secs(5), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+                assert_eq!((&buf[..len], from), (&[i as u8][..], *shard_addr));
+            }
+        });
+        for runtime in shard_runtimes {
+            runtime.shutdown_background();
+        }
+    }
+}
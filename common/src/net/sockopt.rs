@@ -0,0 +1,153 @@
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+
+use cfg_lib::conf;
+use serde::Deserialize;
+use socket2::{Domain, SockRef, Socket, Type};
+
+//未配置backlog时与tokio一致
+const DEFAULT_BACKLOG: i32 = 1024;
+
+/// socket选项，作用于监听socket(sdx::listen_with、ListenConf.socket)与接入的TCP连接
+/// 未配置的选项保持系统默认
+/// # Examples
+///
+///  ```yaml
+/// net:
+///   socket:
+///     reuse_address: true #SO_REUSEADDR 可选
+///     reuse_port: true #SO_REUSEPORT 多进程/多运行时监听同一端口 可选
+///     recv_buffer_size: 1048576 #SO_RCVBUF(字节) 可选
+///     send_buffer_size: 1048576 #SO_SNDBUF(字节) 可选
+///     nodelay: true #TCP_NODELAY，作用于接入的连接 可选
+///     only_v6: false #IPV6_V6ONLY，仅IPv6地址生效 可选
+///     backlog: 1024 #TCP监听队列长度 可选 默认1024
+///     tos: 184 #IPv4 TOS/IPv6 Traffic Class，如DSCP EF(46)<<2 可选
+///     interface: eth0 #SO_BINDTODEVICE，仅经该网卡收发 可选 仅Linux
+///  ```
+/// IPv6地址[::]配合only_v6: false为双栈监听，IPv4对端显示为::ffff:a.b.c.d；按网卡选取监听地址见iface::ListenAddr
+#[derive(Debug, Clone, Default, Deserialize)]
+#[conf(prefix = "net.socket")]
+pub struct SocketOptions {
+    pub reuse_address: Option<bool>,
+    pub reuse_port: Option<bool>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+    pub nodelay: Option<bool>,
+    pub only_v6: Option<bool>,
+    pub backlog: Option<i32>,
+    pub tos: Option<u32>,
+    pub interface: Option<String>,
+}
+
+impl SocketOptions {
+    /// 按选项创建并监听TCP socket，返回std句柄(阻塞模式)
+    pub fn bind_tcp(&self, addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(socket2::Protocol::TCP))?;
+        self.apply(&SockRef::from(&socket), &addr)?;
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog.unwrap_or(DEFAULT_BACKLOG))?;
+        Ok(socket.into())
+    }
+
+    /// 按选项创建并绑定UDP socket，返回std句柄(阻塞模式)
+    pub fn bind_udp(&self, addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(socket2::Protocol::UDP))?;
+        self.apply(&SockRef::from(&socket), &addr)?;
+        socket.bind(&addr.into())?;
+        Ok(socket.into())
+    }
+
+    //接入的TCP连接：TCP_NODELAY、收发缓冲、TOS
+    pub(crate) fn apply_stream(&self, socket: &SockRef, remote_addr: &SocketAddr) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        self.apply_io(socket, remote_addr)
+    }
+
+    fn apply(&self, socket: &SockRef, addr: &SocketAddr) -> io::Result<()> {
+        if let Some(reuse) = self.reuse_address {
+            socket.set_reuse_address(reuse)?;
+        }
+        #[cfg(unix)]
+        if let Some(reuse) = self.reuse_port {
+            socket.set_reuse_port(reuse)?;
+        }
+        if let (Some(only_v6), SocketAddr::V6(_)) = (self.only_v6, addr) {
+            socket.set_only_v6(only_v6)?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.interface {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        self.apply_io(socket, addr)
+    }
+
+    fn apply_io(&self, socket: &SockRef, addr: &SocketAddr) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(tos) = self.tos {
+            match addr {
+                SocketAddr::V4(_) => socket.set_tos(tos)?,
+                SocketAddr::V6(_) => socket.set_tclass_v6(tos)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+//UDP接收数据报的目的地址：IPv4为IP_PKTINFO，IPv6(含双栈)为IPV6_RECVPKTINFO
+pub(crate) fn set_pktinfo(socket: &SockRef, local_addr: &SocketAddr) -> io::Result<()> {
+    let (level, name) = match local_addr {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+    };
+    let enable: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), level, name, &enable as *const libc::c_int as *const libc::c_void, std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_reuse_port() {
+        let addr = SocketAddr::from_str("127.0.0.1:38470").unwrap();
+        let options = SocketOptions { reuse_port: Some(true), nodelay: Some(true), recv_buffer_size: Some(65536), ..Default::default() };
+        let first = options.bind_tcp(addr).unwrap();
+        let second = options.bind_tcp(addr).unwrap();
+        assert!(SocketOptions::default().bind_tcp(addr).is_err());
+        assert!(SockRef::from(&first).reuse_port().unwrap());
+        assert!(SockRef::from(&second).recv_buffer_size().unwrap() >= 65536);
+
+        let udp = options.bind_udp(addr).unwrap();
+        assert!(options.bind_udp(addr).is_ok());
+        drop(udp);
+    }
+
+    #[test]
+    fn test_bind_device() {
+        let options = SocketOptions { interface: Some("lo".to_string()), ..Default::default() };
+        let udp = match options.bind_udp(SocketAddr::from_str("127.0.0.1:0").unwrap()) {
+            Ok(udp) => udp,
+            //内核5.7以下需CAP_NET_RAW，或环境中没有lo，跳过
+            Err(err) if matches!(err.raw_os_error(), Some(libc::EPERM | libc::ENODEV)) => return,
+            Err(err) => panic!("bind to device failed: {err}"),
+        };
+        assert_eq!(SockRef::from(&udp).device().unwrap().as_deref(), Some(&b"lo"[..]));
+    }
+}
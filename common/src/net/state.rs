@@ -1,28 +1,98 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::io;
+use std::time::Duration;
 use bytes::Bytes;
-use dashmap::DashMap;
-use once_cell::sync::Lazy;
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tokio::sync::mpsc::{Sender, Receiver};
+use tokio::sync::mpsc::Receiver;
+use tokio_rustls::TlsStream;
 use constructor::{Get, New, Set};
+use serde::Deserialize;
+use crate::net::codec;
+use crate::net::codec::FrameCodec;
+use crate::net::context::Context;
+use crate::net::proxy::ProxyConf;
+use crate::net::handle::Signal;
+use crate::net::registry::Registry;
+use crate::net::inbound::Inbound;
+use crate::net::admission::{AdmissionConf, Reject};
+use crate::net::ratelimit::RateLimitConf;
+use crate::net::multicast::MulticastConf;
+use crate::net::sockopt::SocketOptions;
+use crate::net::tls::{TlsConf, TlsContext};
 
 
-//TCP连接有状态，需要持有每个连接的句柄
-pub static TCP_HANDLE_MAP: Lazy<Arc<DashMap<Association, Sender<Zip>>>> = Lazy::new(|| {
-    Arc::new(DashMap::new())
-});
 pub const SOCKET_BUFFER_SIZE: usize = 4096;
 pub const CHANNEL_BUFFER_SIZE: usize = 10000;
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+//资源耗尽时accept退避区间，逐次翻倍
+pub const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+pub const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
 pub const UDP: &str = "UDP";
 pub const TCP: &str = "TCP";
 pub const ALL: &str = "ALL";
+pub const TLS: &str = "TLS";
+pub const UNIX: &str = "UNIX";
+pub const UNIXGRAM: &str = "UNIXGRAM";
 
-///type_code = 0 为连接断开
-#[derive(Debug, New, Set, Get)]
+///网络事件；TLS连接建立时peer_subject为对端证书主题
+#[derive(Debug, Set, Get)]
 pub struct Event {
     pub association: Association,
-    pub type_code: u8,
+    pub kind: EventKind,
+    pub peer_subject: Option<String>,
+}
+
+impl Event {
+    pub fn new(association: Association, kind: EventKind) -> Self {
+        Self { association, kind, peer_subject: None }
+    }
+
+    pub fn connected(association: Association, peer_subject: Option<String>) -> Self {
+        Self { association, kind: EventKind::Connected, peer_subject }
+    }
+}
+
+///连接生命周期事件：input为网络层通知程序，output为程序指令网络层
+///除Connected外，每个连接在断开时只通知一次
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EventKind {
+    //input:接入或主动连接成功；output:向association.remote_addr发起连接，应答由监听(association.local_addr)的input返回
+    Connected,
+    //input:主动连接失败
+    ConnectError,
+    //input:对端关闭连接
+    PeerClosed,
+    //input:读取失败或数据无法解码，连接已移除
+    ReadError(io::ErrorKind),
+    //input:写入失败，TCP连接已移除；UDP仅表示发往该对端失败，不影响其他发送
+    //TCP编码失败为InvalidData，连接保持；ALL监听输出的association协议不是TCP/UDP时为Unsupported
+    WriteError(io::ErrorKind),
+    //input:UDP数据报超过ListenConf.buffer_size，已丢弃
+    Truncated,
+    //input:input通道满且Overload::Close，TCP连接已移除
+    Overloaded,
+    //input:接入控制拒绝，TCP连接未登记即关闭，UDP数据报已丢弃
+    Rejected(Reject),
+    //input:超出限速且action为disconnect，TCP连接已移除
+    RateLimited,
+    //input:监听accept受阻(文件句柄/内存耗尽等)，短暂退避后重试；association的远端为监听地址，恢复前仅通知一次
+    AcceptError(io::ErrorKind),
+    //input:连接读/写空闲超时，ListenConf.idle_close时连接已移除
+    IdleTimeout(Idle),
+    //input:本端关闭完成；output:发送完已提交的数据后关闭连接
+    LocallyClosed,
+    //input:本端复位完成，连接已移除；output:立即复位连接(SO_LINGER 0，发送RST)，未写出的数据丢弃
+    Aborted,
+    //input:写方向已关闭，仍可读取直至PeerClosed；output:发送完已提交的数据后关闭写方向(FIN)，之后的输出数据丢弃
+    HalfClosed,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Idle {
+    Read,
+    Write,
 }
 
 #[derive(Debug, Eq, Hash, PartialEq, Clone)]
@@ -30,6 +100,12 @@ pub enum Protocol {
     UDP,
     TCP,
     ALL,
+    //基于TCP的TLS
+    TLS,
+    //Unix域流式socket
+    UNIX,
+    //Unix域数据报socket
+    UNIXGRAM,
 }
 
 impl Protocol {
@@ -38,20 +114,53 @@ impl Protocol {
             Protocol::UDP => { UDP }
             Protocol::TCP => { TCP }
             Protocol::ALL => { ALL }
+            Protocol::TLS => { TLS }
+            Protocol::UNIX => { UNIX }
+            Protocol::UNIXGRAM => { UNIXGRAM }
         }
     }
 }
 
 /// 网络相关(协议，本地地址，本地端口号，远地地址，远地端口号）
-#[derive(Debug, Eq, Hash, PartialEq, New, Set, Get, Clone)]
+/// UNIX/UNIXGRAM的地址为0.0.0.0:0占位，路径与对端凭证见unix
+#[derive(Debug, Eq, Hash, PartialEq, Set, Get, Clone)]
 pub struct Association {
     pub local_addr: SocketAddr,
     pub remote_addr: SocketAddr,
     pub protocol: Protocol,
+    pub unix: Option<UnixPeer>,
+}
+
+impl Association {
+    pub fn new(local_addr: SocketAddr, remote_addr: SocketAddr, protocol: Protocol) -> Self {
+        Self { local_addr, remote_addr, protocol, unix: None }
+    }
+
+    pub fn build_unix(protocol: Protocol, peer: UnixPeer) -> Self {
+        let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        Self { local_addr: unspecified, remote_addr: unspecified, protocol, unix: Some(peer) }
+    }
+}
+
+/// Unix域socket的对端
+/// id:流式连接的序号，区分未绑定路径的对端；数据报为0，以peer_path区分
+/// cred:流式连接的对端凭证(SO_PEERCRED)
+#[derive(Debug, Eq, Hash, PartialEq, New, Get, Clone)]
+pub struct UnixPeer {
+    id: u64,
+    local_path: PathBuf,
+    peer_path: Option<PathBuf>,
+    cred: Option<PeerCred>,
 }
 
-///EVENT:
-/// 0-TCP链接断开；input->对端断开连接；output->主动断开连接
+#[derive(Debug, Eq, Hash, PartialEq, New, Get, Clone, Copy)]
+pub struct PeerCred {
+    pid: Option<i32>,
+    uid: u32,
+    gid: u32,
+}
+
+///EVENT: 见EventKind
 #[derive(Debug)]
 pub enum Zip {
     Data(Package),
@@ -83,26 +192,165 @@ impl Zip {
     }
 }
 
-#[derive(Debug, New, Set, Get)]
+#[derive(Debug, Set, Get)]
 pub struct Package {
     pub association: Association,
     pub data: Bytes,
+    //input:连接上下文，仅TCP/TLS/UNIX连接的数据携带；output忽略
+    pub context: Option<Context>,
+    //input:UDP数据报的目的地址，ListenConf.pktinfo开启时携带；output忽略，回复按路由表选择源地址
+    pub local_ip: Option<IpAddr>,
 }
 
 impl Package {
+    pub fn new(association: Association, data: Bytes) -> Self {
+        Self { association, data, context: None, local_ip: None }
+    }
+
     pub fn get_owned_data(self) -> Bytes {
         self.data
     }
 }
 
+/// 监听配置，可嵌入程序配置由yaml加载；codec不参与反序列化，默认原样透传
+/// # Examples
+///
+///  ```yaml
+/// read_idle_timeout: 60 #TCP读空闲超时(秒) 可选 超时通知IdleTimeout(Read)
+/// write_idle_timeout: 60 #TCP写空闲超时(秒) 可选 超时通知IdleTimeout(Write)
+/// idle_close: true #空闲超时后关闭连接 可选 默认false
+/// write_timeout: 10 #TCP单次写出超时(秒) 可选 超时通知WriteError(TimedOut)并关闭连接
+/// channel_capacity: 10000 #读写通道容量 可选 默认10000
+/// overload: drop_newest #input通道满时的处理 可选 默认block；block|drop_newest|drop_oldest|close
+/// buffer_size: 65535 #读缓冲(字节) 可选 默认4096；TCP单次读取上限，UDP可接收的最大数据报
+/// admission: #接入控制 可选 见AdmissionConf；UNIX/UNIXGRAM不支持按IP的配置(max_per_ip/allow/deny)，启动时报错
+/// rate_limits: #限速 可选 见RateLimitConf；UNIX/UNIXGRAM对端地址为占位，不支持key: ip，启动时报错
+/// broadcast: true #UDP允许发送广播(SO_BROADCAST) 可选 默认false
+/// pktinfo: true #UDP获取数据报的目的地址(IP_PKTINFO/IPV6_PKTINFO)，见Package.local_ip 可选 默认false
+/// multicast: #UDP组播 可选 见MulticastConf
+/// proxy_protocol: #PROXY protocol 可选 见ProxyConf
+/// socket: #socket选项 可选 见SocketOptions(不含net.socket前缀)；配置后监听按选项创建，并作用于接入的TCP连接
+/// tls: #TLS证书 可选 见TlsConf(不含net.tls前缀)；TLS监听未配置时从配置文件net.tls加载
+/// keepalive: #TCP保活 可选 不配置时使用系统默认
+///   time: 30 #空闲多久后开始探测(秒)
+///   interval: 10 #探测间隔(秒) 可选
+///   retries: 3 #探测次数 可选
+///  ```
+#[derive(Debug, Clone, Deserialize, Set, Get)]
+pub struct ListenConf {
+    read_idle_timeout: Option<u64>,
+    write_idle_timeout: Option<u64>,
+    #[serde(default)]
+    idle_close: bool,
+    write_timeout: Option<u64>,
+    channel_capacity: Option<usize>,
+    #[serde(default)]
+    overload: Overload,
+    buffer_size: Option<usize>,
+    admission: Option<AdmissionConf>,
+    #[serde(default)]
+    rate_limits: Vec<RateLimitConf>,
+    #[serde(default)]
+    broadcast: bool,
+    #[serde(default)]
+    pktinfo: bool,
+    multicast: Option<MulticastConf>,
+    proxy_protocol: Option<ProxyConf>,
+    socket: Option<SocketOptions>,
+    tls: Option<TlsConf>,
+    keepalive: Option<KeepaliveConf>,
+    #[serde(skip, default = "codec::raw")]
+    codec: Arc<dyn FrameCodec>,
+}
+
+impl Default for ListenConf {
+    fn default() -> Self {
+        Self {
+            read_idle_timeout: None,
+            write_idle_timeout: None,
+            idle_close: false,
+            write_timeout: None,
+            channel_capacity: None,
+            overload: Overload::Block,
+            buffer_size: None,
+            admission: None,
+            rate_limits: Vec::new(),
+            broadcast: false,
+            pktinfo: false,
+            multicast: None,
+            proxy_protocol: None,
+            socket: None,
+            tls: None,
+            keepalive: None,
+            codec: codec::raw(),
+        }
+    }
+}
+
+impl ListenConf {
+    pub fn with_codec(codec: Arc<dyn FrameCodec>) -> Self {
+        Self { codec, ..Default::default() }
+    }
+
+    pub fn read_idle(&self) -> Option<Duration> {
+        self.read_idle_timeout.filter(|secs| *secs > 0).map(Duration::from_secs)
+    }
+
+    pub fn write_idle(&self) -> Option<Duration> {
+        self.write_idle_timeout.filter(|secs| *secs > 0).map(Duration::from_secs)
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout.filter(|secs| *secs > 0).map(Duration::from_secs)
+    }
+
+    pub fn channel_capacity(&self) -> usize {
+        self.channel_capacity.filter(|capacity| *capacity > 0).unwrap_or(CHANNEL_BUFFER_SIZE)
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.filter(|size| *size > 0).unwrap_or(SOCKET_BUFFER_SIZE)
+    }
+}
+
+///程序处理不及时，input通道满时对新到数据的处理，丢弃计数见Stats::dropped
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overload {
+    //等待通道空闲，暂停读取socket
+    #[default]
+    Block,
+    //丢弃新到数据
+    DropNewest,
+    //丢弃积压中最早的数据；通道满后数据与事件另各积压至多channel_capacity条，积压事件满时等待
+    DropOldest,
+    //丢弃并关闭TCP连接，通知Overloaded；UDP同DropNewest
+    Close,
+}
+
+#[derive(Debug, Clone, Deserialize, New, Set, Get)]
+pub struct KeepaliveConf {
+    time: u64,
+    interval: Option<u64>,
+    retries: Option<u32>,
+}
+
 #[derive(Debug, New, Set, Get)]
 pub struct Gate {
     //监听地址
     local_addr: SocketAddr,
     //从socket读取数据向程序发送
-    input: Sender<Zip>,
+    input: Inbound,
     //从程序中接收数据向socket写入
     output: Receiver<Zip>,
+    //监听配置
+    conf: Arc<ListenConf>,
+    //关闭信号
+    signal: Signal,
+    //TCP连接有状态，登记每个连接的输出句柄
+    registry: Registry,
+    //TLS握手上下文，仅TLS监听持有
+    tls: Option<Arc<TlsContext>>,
 }
 
 impl Gate {
@@ -115,7 +363,8 @@ impl Gate {
 pub enum GateListener {
     Tcp(Gate, TcpListener),
     Udp(Gate, UdpSocket),
-    All((Gate, TcpListener), (Gate, UdpSocket)),
+    All((Gate, TcpListener), Box<(Gate, UdpSocket)>),
+    Tls(Gate, TcpListener),
 }
 
 impl GateListener {
@@ -125,14 +374,25 @@ impl GateListener {
     pub fn build_udp(gate: Gate, udp_socket: UdpSocket) -> Self {
         Self::Udp(gate, udp_socket)
     }
+    pub fn build_tls(gate: Gate, tcp_listener: TcpListener) -> Self {
+        Self::Tls(gate, tcp_listener)
+    }
     pub fn build_all(tg: GateListener, ug: GateListener) -> Self {
         match (tg, ug) {
             (GateListener::Tcp(t_gate, tcp_listener), GateListener::Udp(u_gate, udp_socket)) => {
-                Self::All((t_gate, tcp_listener), (u_gate, udp_socket))
+                Self::All((t_gate, tcp_listener), Box::new((u_gate, udp_socket)))
             }
             _ => panic!("build_all requires a Tcp and Udp listener"),
         }
     }
+
+    pub(crate) fn udp_socket(&self) -> Option<&UdpSocket> {
+        match self {
+            Self::Udp(_, udp_socket) => Some(udp_socket),
+            Self::All(_, udp) => Some(&udp.1),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -140,6 +400,7 @@ pub enum GateAccept {
     //SocketAddr:remote_addr
     Tcp(Gate, SocketAddr, TcpStream),
     Udp(Gate, UdpSocket),
+    Tls(Gate, SocketAddr, Box<TlsStream<TcpStream>>),
 }
 
 impl GateAccept {
@@ -149,4 +410,7 @@ impl GateAccept {
     pub fn accept_udp(gate: Gate, udp_socket: UdpSocket) -> Self {
         Self::Udp(gate, udp_socket)
     }
+    pub fn accept_tls(gate: Gate, remote_addr: SocketAddr, tls_stream: TlsStream<TcpStream>) -> Self {
+        Self::Tls(gate, remote_addr, Box::new(tls_stream))
+    }
 }
\ No newline at end of file
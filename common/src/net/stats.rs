@@ -0,0 +1,481 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use constructor::Get;
+use dashmap::DashMap;
+use log::{debug, error, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::WeakSender;
+use tokio::time;
+
+use exception::{GlobalResult, TransError};
+use crate::net::state::{Association, Protocol, Zip, ACCEPT_BACKOFF_MIN, ACCEPT_BACKOFF_MAX};
+
+//无连接协议(UDP/UNIXGRAM)的association计数空闲超时，超时未收发时移除
+const ASSOCIATION_IDLE_TTL: Duration = Duration::from_secs(300);
+//空闲计数清理间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+//指标接口读取请求的超时
+const SCRAPE_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 监听的统计，由NetHandle::stats获取，snapshot返回当前快照
+/// 按association计数：TCP/UNIX连接关闭时移除；UDP/UNIXGRAM对端不固定，空闲超过5分钟的计数自动移除，也可通过remove清理
+#[derive(Debug, Clone)]
+pub struct Stats {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    local_addr: SocketAddr,
+    protocol: Protocol,
+    total: Counter,
+    associations: DashMap<Association, Arc<Counter>>,
+    //计时起点，Counter.last_active与next_prune为相对毫秒数
+    epoch: Instant,
+    next_prune: AtomicU64,
+    accepted: AtomicU64,
+    closed: AtomicU64,
+    //单个连接的accept失败(对端中止等)，直接跳过
+    accept_errors: AtomicU64,
+    //资源耗尽导致的accept失败，退避后重试
+    accept_exhausted: AtomicU64,
+    //接入控制拒绝的TCP连接及UDP数据报数
+    rejected: AtomicU64,
+    //超出限速的数据数，含被延迟的
+    limited: AtomicU64,
+    //input通道满按过载策略丢弃的数据数
+    dropped: AtomicU64,
+    //通道深度，弱引用不影响通道关闭
+    input: OnceLock<WeakSender<Zip>>,
+    output: OnceLock<WeakSender<Zip>>,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    packets_in: AtomicU64,
+    packets_out: AtomicU64,
+    write_errors: AtomicU64,
+    last_active: AtomicU64,
+}
+
+///收发计数快照；TCP的packets为codec切分/编码的消息数
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Get)]
+pub struct Traffic {
+    bytes_in: u64,
+    bytes_out: u64,
+    packets_in: u64,
+    packets_out: u64,
+    write_errors: u64,
+}
+
+///监听统计快照
+#[derive(Debug, Clone, Get)]
+pub struct Snapshot {
+    local_addr: SocketAddr,
+    protocol: Protocol,
+    total: Traffic,
+    accepted: u64,
+    closed: u64,
+    accept_errors: u64,
+    accept_exhausted: u64,
+    rejected: u64,
+    limited: u64,
+    dropped: u64,
+    input_depth: usize,
+    output_depth: usize,
+    associations: Vec<(Association, Traffic)>,
+}
+
+//无连接协议的association没有关闭事件，计数按空闲清理
+fn connectionless(association: &Association) -> bool {
+    matches!(association.get_protocol(), Protocol::UDP | Protocol::UNIXGRAM)
+}
+
+impl Stats {
+    pub(crate) fn new(local_addr: SocketAddr, protocol: Protocol) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                local_addr,
+                protocol,
+                total: Counter::default(),
+                associations: DashMap::new(),
+                epoch: Instant::now(),
+                next_prune: AtomicU64::new(PRUNE_INTERVAL.as_millis() as u64),
+                accepted: AtomicU64::new(0),
+                closed: AtomicU64::new(0),
+                accept_errors: AtomicU64::new(0),
+                accept_exhausted: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+                limited: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                input: OnceLock::new(),
+                output: OnceLock::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn watch_channels(&self, input: WeakSender<Zip>, output: WeakSender<Zip>) {
+        let _ = self.inner.input.set(input);
+        let _ = self.inner.output.set(output);
+    }
+
+    fn counter(&self, association: &Association) -> Arc<Counter> {
+        let counter = match self.inner.associations.get(association) {
+            Some(counter) => counter.clone(),
+            None => self.inner.associations.entry(association.clone()).or_default().clone(),
+        };
+        if connectionless(association) {
+            let now = self.inner.epoch.elapsed().as_millis() as u64;
+            counter.last_active.store(now, Ordering::Relaxed);
+            self.prune(now);
+        }
+        counter
+    }
+
+    //移除无连接协议空闲的association计数，now为相对epoch的毫秒数；同一时刻仅一个调用方执行清理
+    //按association的协议判断，ALL监听上TCP连接在关闭时移除，UDP对端按空闲清理
+    fn prune(&self, now: u64) {
+        let next_prune = self.inner.next_prune.load(Ordering::Relaxed);
+        if now < next_prune {
+            return;
+        }
+        let next = now + PRUNE_INTERVAL.as_millis() as u64;
+        if self.inner.next_prune.compare_exchange(next_prune, next, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            return;
+        }
+        let ttl = ASSOCIATION_IDLE_TTL.as_millis() as u64;
+        self.inner.associations.retain(|association, counter| !connectionless(association) || now.saturating_sub(counter.last_active.load(Ordering::Relaxed)) < ttl);
+    }
+
+    pub(crate) fn record_in(&self, association: &Association, bytes: usize, packets: u64) {
+        self.inner.total.add_in(bytes, packets);
+        self.counter(association).add_in(bytes, packets);
+    }
+
+    pub(crate) fn record_out(&self, association: &Association, bytes: usize, packets: u64) {
+        self.inner.total.add_out(bytes, packets);
+        self.counter(association).add_out(bytes, packets);
+    }
+
+    pub(crate) fn record_write_error(&self, association: &Association) {
+        self.inner.total.write_errors.fetch_add(1, Ordering::Relaxed);
+        self.counter(association).write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_accepted(&self) {
+        self.inner.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_closed(&self, association: &Association) {
+        self.inner.closed.fetch_add(1, Ordering::Relaxed);
+        self.inner.associations.remove(association);
+    }
+
+    pub(crate) fn record_accept_error(&self, exhausted: bool) {
+        let counter = if exhausted { &self.inner.accept_exhausted } else { &self.inner.accept_errors };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_limited(&self) {
+        self.inner.limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self) {
+        self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> Traffic {
+        self.inner.total.snapshot()
+    }
+
+    pub fn association(&self, association: &Association) -> Option<Traffic> {
+        self.inner.associations.get(association).map(|counter| counter.snapshot())
+    }
+
+    pub fn remove(&self, association: &Association) -> Option<Traffic> {
+        self.inner.associations.remove(association).map(|(_, counter)| counter.snapshot())
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = &self.inner;
+        Snapshot {
+            local_addr: inner.local_addr,
+            protocol: inner.protocol.clone(),
+            total: inner.total.snapshot(),
+            accepted: inner.accepted.load(Ordering::Relaxed),
+            closed: inner.closed.load(Ordering::Relaxed),
+            accept_errors: inner.accept_errors.load(Ordering::Relaxed),
+            accept_exhausted: inner.accept_exhausted.load(Ordering::Relaxed),
+            rejected: inner.rejected.load(Ordering::Relaxed),
+            limited: inner.limited.load(Ordering::Relaxed),
+            dropped: inner.dropped.load(Ordering::Relaxed),
+            input_depth: depth(&inner.input),
+            output_depth: depth(&inner.output),
+            associations: inner.associations.iter().map(|entry| (entry.key().clone(), entry.value().snapshot())).collect(),
+        }
+    }
+}
+
+fn depth(weak: &OnceLock<WeakSender<Zip>>) -> usize {
+    weak.get()
+        .and_then(|weak| weak.upgrade())
+        .map(|tx| tx.max_capacity() - tx.capacity())
+        .unwrap_or(0)
+}
+
+impl Counter {
+    fn add_in(&self, bytes: usize, packets: u64) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_in.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    fn add_out(&self, bytes: usize, packets: u64) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_out.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Traffic {
+        Traffic {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            packets_in: self.packets_in.load(Ordering::Relaxed),
+            packets_out: self.packets_out.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Prometheus文本格式，见write_prometheus
+    pub fn write_prometheus(&self, out: &mut String, per_association: bool) {
+        write_prometheus(std::slice::from_ref(self), out, per_association);
+    }
+
+    fn labels(&self) -> String {
+        format!("local_addr=\"{}\",protocol=\"{}\"", self.local_addr, self.protocol.get_value())
+    }
+}
+
+//指标族：名称、说明、取值
+type TrafficFamily = (&'static str, &'static str, fn(&Traffic) -> u64);
+//指标族：名称、类型、说明、取值
+type ListenerFamily = (&'static str, &'static str, &'static str, fn(&Snapshot) -> u64);
+
+//收发计数指标族
+const TRAFFIC_FAMILIES: [TrafficFamily; 5] = [
+    ("net_bytes_in_total", "Bytes received.", |traffic| traffic.bytes_in),
+    ("net_bytes_out_total", "Bytes sent.", |traffic| traffic.bytes_out),
+    ("net_packets_in_total", "Messages received.", |traffic| traffic.packets_in),
+    ("net_packets_out_total", "Messages sent.", |traffic| traffic.packets_out),
+    ("net_write_errors_total", "Write failures.", |traffic| traffic.write_errors),
+];
+
+//监听级指标族
+const LISTENER_FAMILIES: [ListenerFamily; 7] = [
+    ("net_connections_accepted_total", "counter", "Accepted connections.", |snapshot| snapshot.accepted),
+    ("net_connections_closed_total", "counter", "Closed connections.", |snapshot| snapshot.closed),
+    ("net_rejected_total", "counter", "Connections and datagrams rejected by admission control, PROXY header or TLS handshake checks.", |snapshot| snapshot.rejected),
+    ("net_rate_limited_total", "counter", "Messages exceeding the rate limit.", |snapshot| snapshot.limited),
+    ("net_input_dropped_total", "counter", "Messages dropped by the overload policy.", |snapshot| snapshot.dropped),
+    ("net_input_channel_depth", "gauge", "Queued messages in the input channel.", |snapshot| snapshot.input_depth as u64),
+    ("net_output_channel_depth", "gauge", "Queued messages in the output channel.", |snapshot| snapshot.output_depth as u64),
+];
+
+/// 多个监听的快照输出为Prometheus文本格式，同一指标族的样本连续输出并带# HELP/# TYPE
+/// per_association时输出每个association的收发计数：IP对端以remote_addr区分，Unix域socket以local_path/peer_id/peer_path区分
+pub fn write_prometheus(snapshots: &[Snapshot], out: &mut String, per_association: bool) {
+    let header = |out: &mut String, name: &str, kind: &str, help: &str| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+    };
+    let listeners: Vec<String> = snapshots.iter().map(Snapshot::labels).collect();
+    for (name, help, value) in TRAFFIC_FAMILIES {
+        header(out, name, "counter", help);
+        for (snapshot, listener) in snapshots.iter().zip(&listeners) {
+            let _ = writeln!(out, "{name}{{{listener}}} {}", value(&snapshot.total));
+            if per_association {
+                for (association, traffic) in &snapshot.associations {
+                    let _ = writeln!(out, "{name}{{{listener},{}}} {}", association_labels(association), value(traffic));
+                }
+            }
+        }
+    }
+    header(out, "net_accept_errors_total", "counter", "Accept failures by kind.");
+    for (snapshot, listener) in snapshots.iter().zip(&listeners) {
+        let _ = writeln!(out, "net_accept_errors_total{{{listener},kind=\"connection\"}} {}", snapshot.accept_errors);
+        let _ = writeln!(out, "net_accept_errors_total{{{listener},kind=\"exhausted\"}} {}", snapshot.accept_exhausted);
+    }
+    for (name, kind, help, value) in LISTENER_FAMILIES {
+        header(out, name, kind, help);
+        for (snapshot, listener) in snapshots.iter().zip(&listeners) {
+            let _ = writeln!(out, "{name}{{{listener}}} {}", value(snapshot));
+        }
+    }
+}
+
+//Unix域socket的association无IP地址，按监听路径、连接序号与对端路径区分
+fn association_labels(association: &Association) -> String {
+    match association.get_unix() {
+        None => format!("remote_addr=\"{}\"", association.get_remote_addr()),
+        Some(peer) => {
+            let peer_path = peer.get_peer_path().as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+            format!("local_path=\"{}\",peer_id=\"{}\",peer_path=\"{}\"",
+                    escape(&peer.get_local_path().display().to_string()),
+                    peer.get_id(),
+                    escape(&peer_path))
+        }
+    }
+}
+
+//标签值转义：反斜杠、双引号与换行
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// 启动Prometheus文本指标接口，任意HTTP请求均返回全部监听的当前快照
+/// accept失败时退避后继续监听；读取请求超时的连接直接关闭
+pub async fn serve_prometheus(addr: SocketAddr, stats: Vec<Stats>, per_association: bool) -> GlobalResult<()> {
+    let listener = TcpListener::bind(addr).await.hand_log(|msg| error!("{msg}"))?;
+    debug!("开始监听 Prometheus 指标地址： {}", addr);
+    tokio::spawn(async move {
+        let mut backoff: Option<Duration> = None;
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => {
+                    backoff = None;
+                    stream
+                }
+                Err(err) => {
+                    let delay = backoff.map_or(ACCEPT_BACKOFF_MIN, |delay| (delay * 2).min(ACCEPT_BACKOFF_MAX));
+                    warn!("【Prometheus accept failure】 【Local_addr = {}】 【err = {:?}】 【retry_in = {:?}】", addr, err, delay);
+                    backoff = Some(delay);
+                    time::sleep(delay).await;
+                    continue;
+                }
+            };
+            let snapshots: Vec<Snapshot> = stats.iter().map(Stats::snapshot).collect();
+            let mut body = String::new();
+            write_prometheus(&snapshots, &mut body, per_association);
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                if time::timeout(SCRAPE_READ_TIMEOUT, stream.read(&mut buf)).await.is_err() {
+                    return;
+                }
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    use tokio::net::UdpSocket;
+
+    use crate::net;
+    use crate::net::state::{ListenConf, UnixPeer};
+    use super::*;
+
+    #[test]
+    fn test_snapshot_and_prometheus() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let stats = Stats::new(local_addr, Protocol::TCP);
+        let association = Association::new(local_addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::TCP);
+        stats.record_accepted();
+        stats.record_in(&association, 100, 2);
+        stats.record_out(&association, 40, 1);
+        stats.record_write_error(&association);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(*snapshot.get_accepted(), 1);
+        assert_eq!(*snapshot.get_total().get_packets_in(), 2);
+        assert_eq!(stats.association(&association), Some(*snapshot.get_total()));
+        let mut text = String::new();
+        snapshot.write_prometheus(&mut text, true);
+        assert!(text.contains("net_bytes_in_total{local_addr=\"127.0.0.1:5060\",protocol=\"TCP\"} 100"));
+        assert!(text.contains("net_write_errors_total{local_addr=\"127.0.0.1:5060\",protocol=\"TCP\",remote_addr=\"10.0.0.1:5060\"} 1"));
+        assert!(text.contains("# HELP net_bytes_in_total Bytes received.\n# TYPE net_bytes_in_total counter\n"));
+        assert!(text.contains("# TYPE net_input_channel_depth gauge\n"));
+
+        stats.record_closed(&association);
+        let snapshot = stats.snapshot();
+        assert_eq!(*snapshot.get_closed(), 1);
+        assert!(snapshot.get_associations().is_empty());
+        assert_eq!(*snapshot.get_total().get_bytes_out(), 40);
+    }
+
+    #[test]
+    fn test_prometheus_families() {
+        let tcp = Stats::new(SocketAddr::from_str("127.0.0.1:5060").unwrap(), Protocol::TCP);
+        let unix = Stats::new(SocketAddr::from_str("0.0.0.0:0").unwrap(), Protocol::UNIX);
+        let peer = UnixPeer::new(7, PathBuf::from("/run/net.sock"), None, None);
+        unix.record_in(&Association::build_unix(Protocol::UNIX, peer), 10, 1);
+        let mut text = String::new();
+        write_prometheus(&[tcp.snapshot(), unix.snapshot()], &mut text, true);
+        assert!(text.contains("net_bytes_in_total{local_addr=\"0.0.0.0:0\",protocol=\"UNIX\",local_path=\"/run/net.sock\",peer_id=\"7\",peer_path=\"\"} 10"));
+        //同一指标族仅一组HELP/TYPE，各监听的样本连续输出
+        assert_eq!(text.matches("# TYPE net_bytes_in_total counter").count(), 1);
+        let family = text.lines()
+            .skip_while(|line| !line.starts_with("net_bytes_in_total"))
+            .take_while(|line| line.starts_with("net_bytes_in_total"))
+            .count();
+        assert_eq!(family, 3);
+    }
+
+    #[test]
+    fn test_idle_eviction() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:5060").unwrap();
+        let idle = (ASSOCIATION_IDLE_TTL + PRUNE_INTERVAL * 2).as_millis() as u64;
+        let stats = Stats::new(local_addr, Protocol::UDP);
+        let association = Association::new(local_addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::UDP);
+        stats.record_in(&association, 100, 1);
+        assert!(stats.association(&association).is_some());
+        stats.prune(idle);
+        assert!(stats.association(&association).is_none());
+        assert_eq!(*stats.total().get_bytes_in(), 100);
+
+        //面向连接的协议在关闭时移除，不按空闲清理
+        let stats = Stats::new(local_addr, Protocol::TCP);
+        let association = Association::new(local_addr, SocketAddr::from_str("10.0.0.1:5060").unwrap(), Protocol::TCP);
+        stats.record_in(&association, 100, 1);
+        stats.prune(idle);
+        assert!(stats.association(&association).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_idle_eviction_all() {
+        //ALL监听的UDP对端同样按空闲清理
+        let local_addr = SocketAddr::from_str("127.0.0.1:38448").unwrap();
+        let (_tx, mut rx, handle) = net::init_net(Protocol::ALL, local_addr, ListenConf::default()).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.send_to(b"ping", local_addr).await.unwrap();
+        let association = match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Data(package) => package.get_association().clone(),
+            other => panic!("unexpected {other:?}"),
+        };
+        let stats = handle.stats();
+        assert!(stats.association(&association).is_some());
+        stats.prune(stats.inner.epoch.elapsed().as_millis() as u64 + (ASSOCIATION_IDLE_TTL + PRUNE_INTERVAL).as_millis() as u64);
+        assert!(stats.association(&association).is_none());
+    }
+}
@@ -1,24 +1,60 @@
 use std::net::{SocketAddr};
 use std::time::Duration;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::{io, time};
-use crate::net::state::{Zip, Gate, GateListener, GateAccept, SOCKET_BUFFER_SIZE, Association, Protocol, TCP_HANDLE_MAP, Package, Event};
-use log::{error, debug, info};
-use crate::exception::{GlobalResult, TransError};
-use bytes::Bytes;
-use std::io::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::net::state::{Zip, Gate, ListenConf, Idle, GateListener, GateAccept, SOCKET_BUFFER_SIZE, Association, Protocol, Package, Event, EventKind, CONNECT_TIMEOUT, ACCEPT_BACKOFF_MIN, ACCEPT_BACKOFF_MAX};
+use log::{error, debug, info, warn};
+use crate::exception::{GlobalError, GlobalResult, TransError};
+use crate::exception::code::net_err::TCP_CONNECT_ERROR_CODE;
+use crate::net::proxy;
+use crate::net::admission::Reject;
+use crate::net::handle::Signal;
+use crate::net::registry::{Registry, Slot};
+use crate::net::inbound::Inbound;
+use crate::net::ratelimit::Verdict;
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::IoSlice;
+use crate::net::codec::FrameCodec;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use socket2::{SockRef, TcpKeepalive};
+
+//单次批量写出的最大包数
+const WRITE_BATCH: usize = 64;
 
 //创建tcp监听，并将监听句柄（内含读写句柄）发送出去
 //卸载监听 drop listen？
 pub async fn listen(gate: Gate) -> GlobalResult<GateListener> {
     let local_addr = gate.get_local_addr().clone();
-    let tcp_listener = TcpListener::bind(local_addr).await.hand_log(|msg| error!("{msg}"))?;
+    let tcp_listener = bind(local_addr, gate.get_conf()).await?;
     debug!("开始监听 TCP 地址： {}", local_addr);
     let gate_listener = GateListener::build_tcp(gate, tcp_listener);
     Ok(gate_listener)
 }
+pub async fn listen_tls(gate: Gate) -> GlobalResult<GateListener> {
+    let local_addr = *gate.get_local_addr();
+    let tcp_listener = bind(local_addr, gate.get_conf()).await?;
+    debug!("开始监听 TLS 地址： {}", local_addr);
+    Ok(GateListener::build_tls(gate, tcp_listener))
+}
+
+//配置了ListenConf.socket时按选项创建监听
+async fn bind(local_addr: SocketAddr, conf: &ListenConf) -> GlobalResult<TcpListener> {
+    let tcp_listener = match conf.get_socket() {
+        None => TcpListener::bind(local_addr).await.hand_log(|msg| error!("{msg}"))?,
+        Some(options) => {
+            let std_tcp_listener = options.bind_tcp(local_addr).hand_log(|msg| error!("{msg}"))?;
+            std_tcp_listener.set_nonblocking(true).hand_log(|msg| error!("{msg}"))?;
+            TcpListener::from_std(std_tcp_listener).hand_log(|msg| error!("{msg}"))?
+        }
+    };
+    Ok(tcp_listener)
+}
+
 pub fn listen_by_std(gate: Gate, std_tcp_listener: std::net::TcpListener) -> GlobalResult<GateListener> {
     debug!("tokio监听 TCP 地址： {}", gate.get_local_addr());
     std_tcp_listener.set_nonblocking(true).hand_log(|msg| error!("{msg}"))?;
@@ -28,66 +64,311 @@ pub fn listen_by_std(gate: Gate, std_tcp_listener: std::net::TcpListener) -> Glo
 }
 
 //将连接句柄（内含读写句柄，远端地址等）发送出去
-pub async fn accept(gate: Gate, tcp_listener: &TcpListener, accept_tx: Sender<GateAccept>, lone_output_tx: Sender<Zip>) -> GlobalResult<()> {
-    let local_addr = gate.get_local_addr().clone();
-    let gate_accept = check_accept(tcp_listener).await.map(|(tcp_stream, remote_addr)| {
-        let association = Association::new(local_addr, remote_addr, Protocol::TCP);
-        let map = TCP_HANDLE_MAP.clone();
-        map.insert(association, lone_output_tx);
-        GateAccept::accept_tcp(gate, remote_addr, tcp_stream)
-    })
-        .hand_log(|msg| error!("{:?} : TCP accept has failed too many times.{msg}",local_addr))?;
-    accept_tx.send(gate_accept).await.hand_log(|msg| error!("{msg}"))?;
+//TLS在独立任务中完成握手后再登记连接，避免阻塞接入；可信代理的PROXY头同样在独立任务中读取
+//接入控制的名额在接入时同步预留，握手或读取PROXY头期间占用，登记后或失败时释放
+pub async fn accept(gate: Gate, tcp_listener: &TcpListener, accept_tx: Sender<GateAccept>, lone_output_tx: Sender<Zip>, protocol: Protocol) -> GlobalResult<()> {
+    let local_addr = *gate.get_local_addr();
+    let (mut tcp_stream, peer_addr) = check_accept(tcp_listener, &gate, &protocol).await;
+    configure_stream(&tcp_stream, &peer_addr, gate.get_conf());
+    let proxy_timeout = gate.get_conf().get_proxy_protocol().as_ref()
+        .filter(|proxy_conf| proxy_conf.is_trusted(&peer_addr.ip()))
+        .map(|proxy_conf| proxy_conf.timeout());
+    let reserved = gate.get_conf().get_admission().as_ref().map(|admission| match proxy_timeout {
+        //客户端地址在PROXY头中，此时仅预留总连接数
+        Some(_) => admission.reserve(gate.get_registry()),
+        None => admission.reserve_tcp(gate.get_registry(), &peer_addr.ip()),
+    });
+    let slot = match reserved.transpose() {
+        Ok(slot) => slot,
+        Err(cause) => {
+            warn!("【TCP accept rejected】 【Local_addr = {}】 【Remote_addr = {}】 【Reason = {:?}】", local_addr, peer_addr, cause);
+            reject(&gate, Association::new(local_addr, peer_addr, protocol), cause).await;
+            return Ok(());
+        }
+    };
+    match proxy_timeout {
+        Some(header_timeout) => {
+            tokio::spawn(async move {
+                match timed(Some(header_timeout), proxy::read_header(&mut tcp_stream)).await {
+                    Ok(header) => {
+                        if !gate.get_signal().is_running() {
+                            return;
+                        }
+                        let remote_addr = header.get_source().unwrap_or(peer_addr);
+                        debug!("【TCP proxy】 【Local_addr = {}】 【Proxy_addr = {}】 【Remote_addr = {}】", local_addr, peer_addr, remote_addr);
+                        let reserved = match (gate.get_conf().get_admission(), slot) {
+                            (Some(admission), Some(slot)) => admission.reserve_ip(gate.get_registry(), slot, &remote_addr.ip()).map(Some),
+                            (_, slot) => Ok(slot),
+                        };
+                        match reserved {
+                            Ok(slot) => {
+                                let _ = admit(gate, tcp_stream, remote_addr, slot, accept_tx, lone_output_tx, protocol).await;
+                            }
+                            Err(cause) => {
+                                warn!("【TCP accept rejected】 【Local_addr = {}】 【Remote_addr = {}】 【Reason = {:?}】", local_addr, remote_addr, cause);
+                                reject(&gate, Association::new(local_addr, remote_addr, protocol), cause).await;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        warn!("【TCP proxy header invalid】 【Local_addr = {}】 【Proxy_addr = {}】 【err = {:?}】", local_addr, peer_addr, err);
+                        reject(&gate, Association::new(local_addr, peer_addr, protocol), Reject::InvalidProxy).await;
+                    }
+                }
+            });
+            Ok(())
+        }
+        None => admit(gate, tcp_stream, peer_addr, slot, accept_tx, lone_output_tx, protocol).await,
+    }
+}
+
+//登记已通过接入控制的连接，登记后释放预留名额；remote_addr为客户端地址，经代理时取自PROXY头
+async fn admit(gate: Gate, tcp_stream: TcpStream, remote_addr: SocketAddr, slot: Option<Slot>, accept_tx: Sender<GateAccept>, lone_output_tx: Sender<Zip>, protocol: Protocol) -> GlobalResult<()> {
+    let local_addr = *gate.get_local_addr();
+    let association = Association::new(local_addr, remote_addr, protocol.clone());
+    match protocol {
+        Protocol::TLS => {
+            let context = gate.get_tls().clone()
+                .ok_or_else(|| GlobalError::new_sys_error("TLS listener without tls context", |msg| error!("{msg}")))?;
+            tokio::spawn(async move {
+                match context.accept(tcp_stream).await {
+                    Ok((tls_stream, peer_subject)) => {
+                        //握手期间监听已关闭
+                        if !gate.get_signal().is_running() {
+                            return;
+                        }
+                        gate.get_registry().insert(association.clone(), lone_output_tx);
+                        drop(slot);
+                        gate.get_input().stats().record_accepted();
+                        let _ = gate.get_input().send(Zip::build_event(Event::connected(association, peer_subject))).await.hand_log(|msg| error!("{msg}"));
+                        let _ = accept_tx.send(GateAccept::accept_tls(gate, remote_addr, tls_stream)).await.hand_log(|msg| error!("{msg}"));
+                    }
+                    Err(err) => {
+                        warn!("【TLS handshake failure】 【Local_addr = {}】 【Remote_addr = {}】 【err = {:?}】", local_addr, remote_addr, err);
+                        drop(slot);
+                        reject(&gate, association, Reject::Handshake).await;
+                    }
+                }
+            });
+        }
+        _ => {
+            gate.get_registry().insert(association.clone(), lone_output_tx);
+            drop(slot);
+            gate.get_input().stats().record_accepted();
+            let _ = gate.get_input().send(Zip::build_event(Event::connected(association, None))).await.hand_log(|msg| error!("{msg}"));
+            accept_tx.send(GateAccept::accept_tcp(gate, remote_addr, tcp_stream)).await.hand_log(|msg| error!("{msg}"))?;
+        }
+    }
     Ok(())
 }
 
+async fn reject(gate: &Gate, association: Association, cause: Reject) {
+    gate.get_input().stats().record_rejected();
+    let _ = gate.get_input().send(Zip::build_event(Event::new(association, EventKind::Rejected(cause)))).await.hand_log(|msg| error!("{msg}"));
+}
+
+//主动连接对端，成功后与accept一致交由rw读写；失败则移除预先登记的句柄并通知程序
+pub async fn connect(gate: Gate, association: Association, accept_tx: Sender<GateAccept>) -> GlobalResult<()> {
+    let remote_addr = *association.get_remote_addr();
+    let input = gate.get_input().clone();
+    let registry = gate.get_registry().clone();
+    match dial(gate, &association).await {
+        Ok((gate_accept, peer_subject)) => {
+            debug!("【TCP connect success】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                    association.get_local_addr(),
+                    remote_addr
+                    );
+            input.stats().record_accepted();
+            let _ = input.send(Zip::build_event(Event::connected(association, peer_subject))).await.hand_log(|msg| error!("{msg}"));
+            accept_tx.send(gate_accept).await.hand_log(|msg| error!("{msg}"))?;
+            Ok(())
+        }
+        Err(err) => {
+            close(&registry, association, EventKind::ConnectError, &input).await;
+            Err(GlobalError::new_biz_error(TCP_CONNECT_ERROR_CODE, &format!("TCP connect {remote_addr} failed: {err}"), |msg| error!("{msg}")))
+        }
+    }
+}
+
+async fn dial(gate: Gate, association: &Association) -> GlobalResult<(GateAccept, Option<String>)> {
+    let remote_addr = *association.get_remote_addr();
+    let tcp_stream = time::timeout(CONNECT_TIMEOUT, TcpStream::connect(remote_addr)).await
+        .hand_log(|msg| debug!("{msg}"))?
+        .hand_log(|msg| debug!("{msg}"))?;
+    configure_stream(&tcp_stream, &remote_addr, gate.get_conf());
+    match association.get_protocol() {
+        Protocol::TLS => {
+            let context = gate.get_tls().clone()
+                .ok_or_else(|| GlobalError::new_sys_error("TLS listener without tls context", |msg| error!("{msg}")))?;
+            let (tls_stream, peer_subject) = context.connect(tcp_stream, remote_addr).await?;
+            Ok((GateAccept::accept_tls(gate, remote_addr, tls_stream), peer_subject))
+        }
+        _ => Ok((GateAccept::accept_tcp(gate, remote_addr, tcp_stream), None)),
+    }
+}
+
+//按配置开启TCP保活并设置socket选项，未配置时保持系统默认
+fn configure_stream(tcp_stream: &TcpStream, remote_addr: &SocketAddr, conf: &ListenConf) {
+    if let Some(options) = conf.get_socket() {
+        let _ = options.apply_stream(&SockRef::from(tcp_stream), remote_addr).hand_log(|msg| warn!("set socket options failed: {msg}"));
+    }
+    if let Some(keepalive_conf) = conf.get_keepalive() {
+        let mut keepalive = TcpKeepalive::new().with_time(Duration::from_secs(*keepalive_conf.get_time()));
+        if let Some(interval) = keepalive_conf.get_interval() {
+            keepalive = keepalive.with_interval(Duration::from_secs(*interval));
+        }
+        if let Some(retries) = keepalive_conf.get_retries() {
+            keepalive = keepalive.with_retries(*retries);
+        }
+        let _ = SockRef::from(tcp_stream).set_tcp_keepalive(&keepalive).hand_log(|msg| warn!("set tcp keepalive failed: {msg}"));
+    }
+}
+
 //连接检测
-async fn check_accept(tcp_listener: &TcpListener) -> Result<(TcpStream, SocketAddr), Error> {
-    let mut backoff = 1;
+//接入直到成功：单个连接的错误直接跳过；资源耗尽等其余错误按ACCEPT_BACKOFF_MIN..MAX退避重试，并通知一次AcceptError
+async fn check_accept(tcp_listener: &TcpListener, gate: &Gate, protocol: &Protocol) -> (TcpStream, SocketAddr) {
+    let local_addr = *gate.get_local_addr();
+    let mut backoff: Option<Duration> = None;
     loop {
-        match tcp_listener.accept().await {
-            Ok((tcp_stream, remote_addr)) => {
-                return Ok((tcp_stream, remote_addr));
-            }
-            Err(err) => {
-                if backoff > 32 {
-                    return Err(err);
+        let err = match tcp_listener.accept().await {
+            Ok(accepted) => {
+                if backoff.is_some() {
+                    info!("【TCP accept recovered】 【Local_addr = {}】", local_addr);
                 }
+                return accepted;
             }
+            Err(err) => err,
+        };
+        if is_connection_error(&err) {
+            debug!("【TCP accept skip】 【Local_addr = {}】 【err = {:?}】", local_addr, err);
+            gate.get_input().stats().record_accept_error(false);
+            continue;
         }
-        time::sleep(Duration::from_secs(backoff)).await;
-        backoff *= 2;
+        gate.get_input().stats().record_accept_error(true);
+        let delay = match backoff {
+            None => {
+                error!("【TCP accept exhausted】 【Local_addr = {}】 【err = {:?}】", local_addr, err);
+                let association = Association::new(local_addr, local_addr, protocol.clone());
+                let _ = gate.get_input().send(Zip::build_event(Event::new(association, EventKind::AcceptError(err.kind())))).await.hand_log(|msg| error!("{msg}"));
+                ACCEPT_BACKOFF_MIN
+            }
+            Some(delay) => (delay * 2).min(ACCEPT_BACKOFF_MAX),
+        };
+        backoff = Some(delay);
+        time::sleep(delay).await;
     }
 }
 
+//仅影响单个待接入连接的错误，参考accept(2)：对端中止、网络不可达等应视同EAGAIN
+pub(crate) fn is_connection_error(err: &io::Error) -> bool {
+    if matches!(err.kind(), io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::PermissionDenied) {
+        return true;
+    }
+    matches!(err.raw_os_error(), Some(libc::EPROTO | libc::ENOPROTOOPT | libc::EHOSTDOWN | libc::ENONET | libc::EHOSTUNREACH
+        | libc::EOPNOTSUPP | libc::ENETUNREACH | libc::ENETDOWN))
+}
+
 
 //连接断开测试
-pub async fn read(mut reader: io::ReadHalf<TcpStream>, local_addr: SocketAddr, remote_addr: SocketAddr, tx: Sender<Zip>) {
+//数据直接读入连接缓冲，由codec切分为完整消息后逐个发送，不做拷贝
+//配置读空闲超时时，超时未收到数据通知IdleTimeout(Read)，idle_close时关闭连接
+//写任务退出(write_done)即连接已关闭，读任务随之退出
+pub async fn read<R: AsyncRead + Unpin>(mut reader: R, association: Association, tx: Inbound, conf: Arc<ListenConf>, mut signal: Signal, registry: Registry, mut write_done: oneshot::Receiver<()>) {
+    let local_addr = *association.get_local_addr();
+    let remote_addr = *association.get_remote_addr();
+    let codec = conf.get_codec();
+    let read_idle = conf.read_idle();
+    let buffer_size = conf.buffer_size();
+    let mut buffer = BytesMut::with_capacity(buffer_size);
+    //codec已查找过的缓冲偏移，避免每次读取后从头查找
+    let mut scanned = 0;
+    let context = registry.context(&association);
     loop {
-        let mut buf = [0u8; SOCKET_BUFFER_SIZE];
-        match reader.read(&mut buf[..]).await {
+        buffer.reserve(buffer_size);
+        let read = async {
+            match read_idle {
+                None => Ok(reader.read_buf(&mut buffer).await),
+                Some(idle) => time::timeout(idle, reader.read_buf(&mut buffer)).await,
+            }
+        };
+        let res = tokio::select! {
+            res = read => res,
+            //监听关闭，由写任务关闭连接并通知
+            _ = signal.stopping() => return,
+            _ = &mut write_done => return,
+        };
+        let res = match res {
+            Ok(res) => res,
+            Err(_) => {
+                debug!("【TCP read idle timeout】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                        local_addr,
+                        remote_addr
+                        );
+                if *conf.get_idle_close() {
+                    close(&registry, association, EventKind::IdleTimeout(Idle::Read), &tx).await;
+                    return;
+                }
+                let zip = Zip::build_event(Event::new(association.clone(), EventKind::IdleTimeout(Idle::Read)));
+                let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                continue;
+            }
+        };
+        match res {
             Ok(len) => {
                 if len != 0 {
+                    tx.stats().record_in(&association, len, 0);
                     debug!("【TCP read success】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【len = {}】",
                             local_addr,
                             remote_addr,
                             len
                             );
-                    let association = Association::new(local_addr, remote_addr, Protocol::TCP);
-                    let zip = Zip::build_data(Package::new(association, Bytes::copy_from_slice(&buf[..len])));
-                    let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                    loop {
+                        match codec.decode_from(&mut buffer, &mut scanned) {
+                            Ok(Some(frame)) => {
+                                tx.stats().record_in(&association, 0, 1);
+                                match tx.throttle(&association).await {
+                                    Verdict::Drop => continue,
+                                    Verdict::Disconnect => {
+                                        warn!("【TCP rate limited】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                                            local_addr,
+                                            remote_addr
+                                            );
+                                        close(&registry, association, EventKind::RateLimited, &tx).await;
+                                        return;
+                                    }
+                                    _ => {}
+                                }
+                                let mut package = Package::new(association.clone(), frame);
+                                package.context = context.clone();
+                                if !tx.deliver(Zip::build_data(package)).await {
+                                    warn!("【TCP input overloaded】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                                        local_addr,
+                                        remote_addr
+                                        );
+                                    close(&registry, association, EventKind::Overloaded, &tx).await;
+                                    return;
+                                }
+                            }
+                            Ok(None) => { break; }
+                            Err(_) => {
+                                warn!("【TCP frame decode failure】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【buffered = {}】",
+                                    local_addr,
+                                    remote_addr,
+                                    buffer.len()
+                                    );
+                                close(&registry, association, EventKind::ReadError(io::ErrorKind::InvalidData), &tx).await;
+                                return;
+                            }
+                        }
+                    }
                 } else {
-                    debug!("【TCP connection disconnected】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                    debug!("【TCP connection disconnected】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【discard = {}】",
                             local_addr,
-                            remote_addr
+                            remote_addr,
+                            buffer.len()
                             );
-                    let association = Association::new(local_addr, remote_addr, Protocol::TCP);
-
-                    //断开连接移除持有句柄
-                    let map = TCP_HANDLE_MAP.clone();
-                    map.remove(&association);
-                    let zip = Zip::build_event(Event::new(association, 0u8));
-                    let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                    close(&registry, association, EventKind::PeerClosed, &tx).await;
                     break;
                 }
             }
@@ -99,45 +380,442 @@ pub async fn read(mut reader: io::ReadHalf<TcpStream>, local_addr: SocketAddr, r
                             local_addr.to_string(),
                             err,
                             );
+                close(&registry, association, EventKind::ReadError(err.kind()), &tx).await;
                 break;
             }
         }
     }
 }
 
-pub async fn write(mut writer: io::WriteHalf<TcpStream>,mut rx: Receiver<Zip>) {
-    while let Some(zip) = rx.recv().await {
+//断开连接移除持有句柄，由成功移除的一方通知程序，保证每个连接只通知一次断开
+pub async fn close(registry: &Registry, association: Association, kind: EventKind, tx: &Inbound) {
+    if registry.remove(&association).is_some() {
+        tx.stats().record_closed(&association);
+        let zip = Zip::build_event(Event::new(association, kind));
+        let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+    }
+}
+
+//配置写空闲超时时，超时无输出数据通知IdleTimeout(Write)，idle_close时关闭连接
+//已排队的数据合并为一次批量写出；配置写超时时，写出或关闭超时视为WriteError(TimedOut)并关闭连接
+//返回true表示收到Aborted指令，调用方需在释放连接前设置SO_LINGER 0
+pub async fn write<W: AsyncWrite + Unpin>(mut writer: W, association: Association, mut rx: Receiver<Zip>, tx: Inbound, conf: Arc<ListenConf>, mut signal: Signal, registry: Registry) -> bool {
+    let local_addr = *association.get_local_addr();
+    let remote_addr = *association.get_remote_addr();
+    let codec = conf.get_codec();
+    let write_idle = conf.write_idle();
+    let write_timeout = conf.write_timeout();
+    let mut buffer = BytesMut::with_capacity(SOCKET_BUFFER_SIZE);
+    //批量读取时遇到的事件，写出后再处理
+    let mut deferred = None;
+    let mut half_closed = false;
+    loop {
+        let zip = match deferred.take() {
+            Some(zip) => zip,
+            None => {
+                let recv = async {
+                    match write_idle {
+                        None => Ok(rx.recv().await),
+                        Some(idle) => time::timeout(idle, rx.recv()).await,
+                    }
+                };
+                let res = tokio::select! {
+                    res = recv => res,
+                    _ = signal.closing() => {
+                        let _ = timed(write_timeout, writer.shutdown()).await;
+                        close(&registry, association, EventKind::LocallyClosed, &tx).await;
+                        return false;
+                    }
+                };
+                match res {
+                    Ok(Some(zip)) => zip,
+                    Ok(None) => break,
+                    Err(_) => {
+                        debug!("【TCP write idle timeout】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                                local_addr,
+                                remote_addr
+                                );
+                        if *conf.get_idle_close() {
+                            let _ = timed(write_timeout, writer.shutdown()).await;
+                            close(&registry, association, EventKind::IdleTimeout(Idle::Write), &tx).await;
+                            return false;
+                        }
+                        let zip = Zip::build_event(Event::new(association.clone(), EventKind::IdleTimeout(Idle::Write)));
+                        let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                        continue;
+                    }
+                }
+            }
+        };
         match zip {
+            Zip::Data(_) if half_closed => {
+                warn!("【TCP】写方向已关闭，丢弃输出数据 => {:?}", &association);
+            }
             Zip::Data(package) => {
-                let bytes = package.get_data();
-                let local_addr = package.get_association().get_local_addr();
-                let remote_addr = package.get_association().get_remote_addr();
-                match writer.write(&*bytes).await {
+                let mut frames = VecDeque::new();
+                let mut rejected = usize::from(!encode_frame(codec, package, &mut buffer, &mut frames));
+                while frames.len() < WRITE_BATCH {
+                    match rx.try_recv() {
+                        Ok(Zip::Data(package)) => rejected += usize::from(!encode_frame(codec, package, &mut buffer, &mut frames)),
+                        Ok(event) => {
+                            deferred = Some(event);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                //编码失败的包不写出，连接保持，逐包通知WriteError(InvalidData)
+                if rejected > 0 {
+                    warn!("【TCP frame encode failure】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【dropped = {}】",
+                        local_addr,
+                        remote_addr,
+                        rejected
+                        );
+                    for _ in 0..rejected {
+                        tx.stats().record_write_error(&association);
+                        let zip = Zip::build_event(Event::new(association.clone(), EventKind::WriteError(io::ErrorKind::InvalidData)));
+                        let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                    }
+                }
+                //复位时未写出的数据一并丢弃
+                let aborting = matches!(&deferred, Some(Zip::Event(event)) if event.get_kind() == &EventKind::Aborted);
+                if frames.is_empty() || aborting {
+                    continue;
+                }
+                let count = frames.len();
+                match timed(write_timeout, write_frames(&mut writer, frames)).await {
                     Ok(len) => {
-                        debug!("【TCP write success】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【len = {}】",
+                        tx.stats().record_out(&association, len, count as u64);
+                        debug!("【TCP write success】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【len = {}】 【packages = {}】",
                             local_addr,
                             remote_addr,
-                            len
+                            len,
+                            count
                             );
                     }
-                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                        continue;
-                    }
                     Err(err) => {
                         error!("【TCP write failure】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【err = {:?}】",
                             local_addr,
                             remote_addr,
                             err
                             );
-                        break;
+                        tx.stats().record_write_error(&association);
+                        close(&registry, association, EventKind::WriteError(err.kind()), &tx).await;
+                        return false;
                     }
                 }
             }
             Zip::Event(event) => {
-                let _ = writer.shutdown();
-                let map = TCP_HANDLE_MAP.clone();
-                map.remove(event.get_association());
+                match event.get_kind() {
+                    EventKind::LocallyClosed => {
+                        let _ = timed(write_timeout, writer.shutdown()).await;
+                        close(&registry, association, EventKind::LocallyClosed, &tx).await;
+                        return false;
+                    }
+                    EventKind::Aborted => {
+                        debug!("【TCP abort】 【Local_addr = {:?}】 【Remote_addr = {:?}】",
+                            local_addr,
+                            remote_addr
+                            );
+                        close(&registry, association, EventKind::Aborted, &tx).await;
+                        return true;
+                    }
+                    EventKind::HalfClosed if !half_closed => {
+                        half_closed = true;
+                        if let Err(err) = timed(write_timeout, writer.shutdown()).await {
+                            tx.stats().record_write_error(&association);
+                            close(&registry, association, EventKind::WriteError(err.kind()), &tx).await;
+                            return false;
+                        }
+                        let _ = tx.send(Zip::build_event(Event::new(association.clone(), EventKind::HalfClosed))).await.hand_log(|msg| error!("{msg}"));
+                    }
+                    other => {
+                        warn!("【TCP】不支持的输出事件 => {:?} : {:?}", &association, other);
+                    }
+                }
+            }
+        }
+    }
+    false
+}
+
+//编码失败的包丢弃并返回false，由调用方上报；空帧不参与写出
+fn encode_frame(codec: &Arc<dyn FrameCodec>, package: Package, buffer: &mut BytesMut, frames: &mut VecDeque<Bytes>) -> bool {
+    if codec.encode(package.get_owned_data(), buffer).is_err() {
+        buffer.clear();
+        return false;
+    }
+    let frame = buffer.split().freeze();
+    if !frame.is_empty() {
+        frames.push_back(frame);
+    }
+    true
+}
+
+//写出全部帧：write_vectored合并系统调用，部分写入时从剩余位置继续；不支持vectored的writer逐帧写出
+async fn write_frames<W: AsyncWrite + Unpin>(writer: &mut W, mut frames: VecDeque<Bytes>) -> io::Result<usize> {
+    let mut total = 0;
+    while !frames.is_empty() {
+        let slices: Vec<IoSlice> = frames.iter().map(|frame| IoSlice::new(frame)).collect();
+        let mut len = writer.write_vectored(&slices).await?;
+        if len == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        total += len;
+        while len > 0 {
+            let front = &mut frames[0];
+            if len < front.len() {
+                front.advance(len);
+                break;
             }
+            len -= front.len();
+            frames.pop_front();
         }
     }
-}
\ No newline at end of file
+    writer.flush().await?;
+    Ok(total)
+}
+
+async fn timed<T>(timeout: Option<Duration>, fut: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    match timeout {
+        None => fut.await,
+        Some(timeout) => time::timeout(timeout, fut).await.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use crate::net;
+    use crate::net::admission::AdmissionConf;
+    use crate::net::proxy::ProxyConf;
+    use super::*;
+
+    async fn recv_event(rx: &mut Receiver<Zip>) -> Event {
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Event(event) => event,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38450").unwrap();
+        let (tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, ListenConf::default()).await.unwrap();
+
+        let peer = TcpStream::connect(local_addr).await.unwrap();
+        let event = recv_event(&mut rx).await;
+        assert_eq!(event.get_kind(), &EventKind::Connected);
+        assert_eq!(event.get_association().get_remote_addr(), &peer.local_addr().unwrap());
+        drop(peer);
+        let closed = recv_event(&mut rx).await;
+        assert_eq!(closed.get_kind(), &EventKind::PeerClosed);
+        assert!(!handle.registry().contains(closed.get_association()));
+
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let event = recv_event(&mut rx).await;
+        assert_eq!(event.get_kind(), &EventKind::Connected);
+        tx.send(Zip::build_event(Event::new(event.get_association().clone(), EventKind::LocallyClosed))).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::LocallyClosed);
+        let mut buf = [0u8; 8];
+        assert_eq!(time::timeout(Duration::from_secs(5), peer.read(&mut buf)).await.unwrap().unwrap(), 0);
+        assert!(time::timeout(Duration::from_millis(200), rx.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_batch_and_timeout() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38455").unwrap();
+        let (tx, mut rx, _handle) = net::init_net(Protocol::TCP, local_addr, ListenConf::default()).await.unwrap();
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let association = recv_event(&mut rx).await.get_association().clone();
+        let mut expected = Vec::new();
+        for i in 0..200 {
+            let data = format!("{i:04}");
+            expected.extend_from_slice(data.as_bytes());
+            tx.send(Zip::build_data(Package::new(association.clone(), bytes::Bytes::from(data)))).await.unwrap();
+        }
+        let mut received = vec![0u8; expected.len()];
+        time::timeout(Duration::from_secs(5), peer.read_exact(&mut received)).await.unwrap().unwrap();
+        assert_eq!(received, expected);
+
+        //对端不读取，写满缓冲后超时关闭
+        let local_addr = SocketAddr::from_str("127.0.0.1:38456").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_write_timeout(Some(1));
+        let (tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+        let _peer = TcpStream::connect(local_addr).await.unwrap();
+        let association = recv_event(&mut rx).await.get_association().clone();
+        let chunk = bytes::Bytes::from(vec![0u8; 1 << 20]);
+        for _ in 0..64 {
+            tx.send(Zip::build_data(Package::new(association.clone(), chunk.clone()))).await.unwrap();
+        }
+        let event = time::timeout(Duration::from_secs(10), rx.recv()).await.unwrap().unwrap();
+        match event {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::WriteError(io::ErrorKind::TimedOut)),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(!handle.registry().contains(&association));
+
+        //编码失败：通知WriteError(InvalidData)，连接保持，后续数据正常写出
+        let local_addr = SocketAddr::from_str("127.0.0.1:38459").unwrap();
+        let conf = ListenConf::with_codec(Arc::new(crate::net::codec::LengthCodec::new(1, 1024)));
+        let (tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let association = recv_event(&mut rx).await.get_association().clone();
+        tx.send(Zip::build_data(Package::new(association.clone(), bytes::Bytes::from(vec![0u8; 300])))).await.unwrap();
+        tx.send(Zip::build_data(Package::new(association.clone(), bytes::Bytes::from("ok")))).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::WriteError(io::ErrorKind::InvalidData));
+        let mut buf = [0u8; 3];
+        time::timeout(Duration::from_secs(5), peer.read_exact(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf, b"\x02ok");
+        assert!(handle.registry().contains(&association));
+    }
+
+    #[tokio::test]
+    async fn test_close_commands() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38457").unwrap();
+        let (tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, ListenConf::default()).await.unwrap();
+
+        //半关闭：对端读到EOF，仍可向本端发送
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let association = recv_event(&mut rx).await.get_association().clone();
+        tx.send(Zip::build_data(Package::new(association.clone(), bytes::Bytes::from("bye")))).await.unwrap();
+        tx.send(Zip::build_event(Event::new(association.clone(), EventKind::HalfClosed))).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::HalfClosed);
+        let mut buf = Vec::new();
+        time::timeout(Duration::from_secs(5), peer.read_to_end(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(buf, b"bye");
+        peer.write_all(b"ok").await.unwrap();
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Data(package) => assert_eq!(package.get_data(), &bytes::Bytes::from("ok")),
+            other => panic!("unexpected {other:?}"),
+        }
+        drop(peer);
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::PeerClosed);
+
+        //复位：对端收到RST
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let association = recv_event(&mut rx).await.get_association().clone();
+        tx.send(Zip::build_event(Event::new(association.clone(), EventKind::Aborted))).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Aborted);
+        assert!(!handle.registry().contains(&association));
+        let mut buf = [0u8; 8];
+        let err = time::timeout(Duration::from_secs(5), peer.read(&mut buf)).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38458").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_proxy_protocol(Some(ProxyConf::new(vec!["127.0.0.0/8".parse().unwrap()], Some(1)).unwrap()));
+        let (_tx, mut rx, _handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        peer.write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 5060 38458\r\nREGISTER").await.unwrap();
+        let event = recv_event(&mut rx).await;
+        assert_eq!(event.get_kind(), &EventKind::Connected);
+        assert_eq!(event.get_association().get_remote_addr(), &SocketAddr::from_str("203.0.113.7:5060").unwrap());
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Data(package) => assert_eq!(package.get_data(), &bytes::Bytes::from("REGISTER")),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        //缺少PROXY头：超时后拒绝
+        let _peer = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Rejected(Reject::InvalidProxy));
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38451").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_read_idle_timeout(Some(1));
+        let (_tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+        let _peer = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Connected);
+        let event = recv_event(&mut rx).await;
+        assert_eq!(event.get_kind(), &EventKind::IdleTimeout(Idle::Read));
+        assert!(handle.registry().contains(event.get_association()));
+
+        let local_addr = SocketAddr::from_str("127.0.0.1:38452").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_write_idle_timeout(Some(1));
+        conf.set_idle_close(true);
+        let (_tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Connected);
+        let event = recv_event(&mut rx).await;
+        assert_eq!(event.get_kind(), &EventKind::IdleTimeout(Idle::Write));
+        assert!(!handle.registry().contains(event.get_association()));
+        let mut buf = [0u8; 8];
+        assert_eq!(time::timeout(Duration::from_secs(5), peer.read(&mut buf)).await.unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38453").unwrap();
+        let (tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, ListenConf::default()).await.unwrap();
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        let event = recv_event(&mut rx).await;
+        assert_eq!(event.get_kind(), &EventKind::Connected);
+        let association = event.get_association().clone();
+        tx.send(Zip::build_data(Package::new(association.clone(), bytes::Bytes::from("bye")))).await.unwrap();
+        time::timeout(Duration::from_secs(5), handle.shutdown(true)).await.unwrap();
+        assert!(!handle.is_running());
+
+        let mut buf = Vec::new();
+        time::timeout(Duration::from_secs(5), peer.read_to_end(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..], b"bye");
+        let closed = recv_event(&mut rx).await;
+        assert_eq!(closed.get_kind(), &EventKind::LocallyClosed);
+        assert_eq!(closed.get_association(), &association);
+        assert!(handle.registry().is_empty());
+        assert!(time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().is_none());
+        assert!(TcpStream::connect(local_addr).await.is_err());
+    }
+
+    #[test]
+    fn test_accept_error_classify() {
+        assert!(is_connection_error(&io::Error::from(io::ErrorKind::ConnectionAborted)));
+        assert!(is_connection_error(&io::Error::from_raw_os_error(libc::EPROTO)));
+        assert!(!is_connection_error(&io::Error::from_raw_os_error(libc::EMFILE)));
+        assert!(!is_connection_error(&io::Error::from_raw_os_error(libc::ENOBUFS)));
+    }
+
+    #[tokio::test]
+    async fn test_admission() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38454").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_admission(Some(AdmissionConf::new(None, Some(1), Vec::new(), Vec::new())));
+        let (_tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+        let _peer = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Connected);
+
+        let mut rejected = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Rejected(Reject::MaxPerIp));
+        let mut buf = [0u8; 8];
+        assert_eq!(time::timeout(Duration::from_secs(5), rejected.read(&mut buf)).await.unwrap().unwrap(), 0);
+        assert_eq!(handle.registry().len(), 1);
+        assert_eq!(*handle.stats().snapshot().get_rejected(), 1);
+
+        //读取PROXY头期间占用名额，未登记的连接同样计入上限
+        let local_addr = SocketAddr::from_str("127.0.0.1:38449").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_admission(Some(AdmissionConf::new(Some(1), None, Vec::new(), Vec::new())));
+        conf.set_proxy_protocol(Some(ProxyConf::new(vec!["127.0.0.0/8".parse().unwrap()], Some(2)).unwrap()));
+        let (_tx, mut rx, handle) = net::init_net(Protocol::TCP, local_addr, conf).await.unwrap();
+        let _pending = TcpStream::connect(local_addr).await.unwrap();
+        let _rejected = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Rejected(Reject::MaxConnections));
+        assert_eq!(handle.registry().len(), 0);
+        //PROXY头超时后释放名额
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Rejected(Reject::InvalidProxy));
+        let mut peer = TcpStream::connect(local_addr).await.unwrap();
+        peer.write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 5060 38449\r\n").await.unwrap();
+        assert_eq!(recv_event(&mut rx).await.get_kind(), &EventKind::Connected);
+        assert_eq!(handle.registry().reserved(), 0);
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{debug, error};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use cfg_lib::{conf};
+use exception::{GlobalError, GlobalResult, TransError};
+use exception::code::net_err::TCP_CONNECT_ERROR_CODE;
+use crate::net::state::CONNECT_TIMEOUT;
+
+/// TLS证书配置，每个监听独立持有(见ListenConf.tls)；监听未配置时从配置文件net.tls加载
+/// # Examples
+///
+///  ```yaml
+/// net:
+///   tls:
+///     cert_file: ./certs/server.crt #本端证书链(PEM) 必选
+///     key_file: ./certs/server.key #本端私钥(PEM) 必选
+///     ca_file: ./certs/ca.crt #信任的CA证书(PEM) 可选；配置后校验对端证书；主动连接时必选
+///     client_auth: true #接入时要求对端提供证书 可选 默认false；需配置ca_file
+///     server_name: sip.example.com #主动连接时校验的服务端名称 可选 默认对端IP
+///  ```
+#[derive(Debug, Clone, Deserialize)]
+#[conf(prefix = "net.tls")]
+pub struct TlsConf {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub ca_file: Option<PathBuf>,
+    #[serde(default)]
+    pub client_auth: bool,
+    pub server_name: Option<String>,
+}
+
+/// 由TlsConf构建的握手上下文，监听时构建并由该监听的Gate持有
+pub struct TlsContext {
+    acceptor: TlsAcceptor,
+    connector: Option<TlsConnector>,
+    server_name: Option<String>,
+}
+
+impl std::fmt::Debug for TlsContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsContext")
+            .field("connector", &self.connector.is_some())
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsContext {
+    pub fn build(conf: &TlsConf) -> GlobalResult<Self> {
+        if conf.client_auth && conf.ca_file.is_none() {
+            Err(GlobalError::new_sys_error("TLS client_auth requires ca_file", |msg| error!("{msg}")))?
+        }
+        let certs = load_certs(&conf.cert_file)?;
+        let key = load_key(&conf.key_file)?;
+        let roots = conf.ca_file.as_deref().map(load_roots).transpose()?;
+        let provider = Arc::new(ring::default_provider());
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions().hand_log(|msg| error!("{msg}"))?;
+        let builder = match &roots {
+            None => builder.with_no_client_auth(),
+            Some(roots) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots.clone()), provider.clone());
+                let verifier = if conf.client_auth { verifier } else { verifier.allow_unauthenticated() };
+                let verifier = verifier.build().hand_log(|msg| error!("{msg}"))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+        let server_config = builder.with_single_cert(certs.clone(), key.clone_key()).hand_log(|msg| error!("{msg}"))?;
+
+        let connector = match roots {
+            None => None,
+            Some(roots) => {
+                let client_config = ClientConfig::builder_with_provider(provider)
+                    .with_safe_default_protocol_versions().hand_log(|msg| error!("{msg}"))?
+                    .with_root_certificates(roots)
+                    .with_client_auth_cert(certs, key).hand_log(|msg| error!("{msg}"))?;
+                Some(TlsConnector::from(Arc::new(client_config)))
+            }
+        };
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            connector,
+            server_name: conf.server_name.clone(),
+        })
+    }
+
+    //服务端握手，返回对端证书主题(对端未提供证书时为None)；失败由调用方通知Rejected(Handshake)
+    pub async fn accept(&self, tcp_stream: TcpStream) -> GlobalResult<(TlsStream<TcpStream>, Option<String>)> {
+        let tls_stream = time::timeout(CONNECT_TIMEOUT, self.acceptor.accept(tcp_stream)).await
+            .hand_log(|msg| debug!("TLS handshake timeout: {msg}"))?
+            .hand_log(|msg| debug!("TLS handshake failed: {msg}"))?;
+        let peer_subject = peer_subject(tls_stream.get_ref().1.peer_certificates());
+        Ok((TlsStream::Server(tls_stream), peer_subject))
+    }
+
+    //客户端握手，返回服务端证书主题；失败由调用方通知ConnectError
+    pub async fn connect(&self, tcp_stream: TcpStream, remote_addr: SocketAddr) -> GlobalResult<(TlsStream<TcpStream>, Option<String>)> {
+        let connector = self.connector.as_ref()
+            .ok_or_else(|| GlobalError::new_biz_error(TCP_CONNECT_ERROR_CODE, "TLS connect requires tls.ca_file", |msg| error!("{msg}")))?;
+        let server_name = match &self.server_name {
+            None => ServerName::IpAddress(remote_addr.ip().into()),
+            Some(name) => ServerName::try_from(name.clone()).hand_log(|msg| error!("{msg}"))?,
+        };
+        let tls_stream = time::timeout(CONNECT_TIMEOUT, connector.connect(server_name, tcp_stream)).await
+            .hand_log(|msg| debug!("TLS handshake timeout: {msg}"))?
+            .hand_log(|msg| debug!("TLS handshake failed: {msg}"))?;
+        let peer_subject = peer_subject(tls_stream.get_ref().1.peer_certificates());
+        Ok((TlsStream::Client(tls_stream), peer_subject))
+    }
+}
+
+//监听时构建上下文，监听未配置tls时从配置文件加载
+pub(crate) fn context(conf: Option<&TlsConf>) -> GlobalResult<Arc<TlsContext>> {
+    let context = match conf {
+        Some(conf) => TlsContext::build(conf)?,
+        None => TlsContext::build(&TlsConf::conf())?,
+    };
+    Ok(Arc::new(context))
+}
+
+fn peer_subject(certs: Option<&[CertificateDer<'_>]>) -> Option<String> {
+    let cert = certs?.first()?;
+    X509Certificate::from_der(cert.as_ref()).ok().map(|(_, x509)| x509.subject().to_string())
+}
+
+fn open(path: &Path) -> GlobalResult<BufReader<File>> {
+    let file = File::open(path).hand_log(|msg| error!("open {path:?} failed: {msg}"))?;
+    Ok(BufReader::new(file))
+}
+
+fn load_certs(path: &Path) -> GlobalResult<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .hand_log(|msg| error!("{msg}"))?;
+    if certs.is_empty() {
+        Err(GlobalError::new_sys_error(&format!("no certificate found in {path:?}"), |msg| error!("{msg}")))?
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> GlobalResult<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .hand_log(|msg| error!("{msg}"))?
+        .ok_or_else(|| GlobalError::new_sys_error(&format!("no private key found in {path:?}"), |msg| error!("{msg}")))
+}
+
+fn load_roots(path: &Path) -> GlobalResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).hand_log(|msg| error!("{msg}"))?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use tokio::io::AsyncWriteExt;
+
+    use crate::net;
+    use crate::net::admission::Reject;
+    use crate::net::state::{Association, Event, EventKind, ListenConf, Package, Protocol, Zip};
+    use super::*;
+
+    //测试证书目录，结束时删除
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn gen_certs(name: &str) -> (TempDir, TlsConf) {
+        let dir = TempDir(std::env::temp_dir().join(format!("pig_tls_{}_{}", name, std::process::id())));
+        std::fs::create_dir_all(&dir.0).unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.distinguished_name.push(DnType::CommonName, "pig-ca");
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let mut params = CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, "pig-node");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        std::fs::write(dir.0.join("ca.crt"), ca.pem()).unwrap();
+        std::fs::write(dir.0.join("node.crt"), cert.pem()).unwrap();
+        std::fs::write(dir.0.join("node.key"), key.serialize_pem()).unwrap();
+        let conf = TlsConf {
+            cert_file: dir.0.join("node.crt"),
+            key_file: dir.0.join("node.key"),
+            ca_file: Some(dir.0.join("ca.crt")),
+            client_auth: true,
+            server_name: None,
+        };
+        (dir, conf)
+    }
+
+    fn listen_conf(tls: &TlsConf) -> ListenConf {
+        let mut conf = ListenConf::default();
+        conf.set_tls(Some(tls.clone()));
+        conf
+    }
+
+    async fn recv(rx: &mut tokio::sync::mpsc::Receiver<Zip>) -> Zip {
+        time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tls_connect_and_accept() {
+        let (_dir, tls) = gen_certs("connect");
+        let server_addr = SocketAddr::from_str("127.0.0.1:38443").unwrap();
+        let client_addr = SocketAddr::from_str("127.0.0.1:38444").unwrap();
+        let (_server_tx, mut server_rx, _) = net::init_net(Protocol::TLS, server_addr, listen_conf(&tls)).await.unwrap();
+        let (client_tx, mut client_rx, _) = net::init_net(Protocol::TLS, client_addr, listen_conf(&tls)).await.unwrap();
+
+        let association = Association::new(client_addr, server_addr, Protocol::TLS);
+        client_tx.send(Zip::build_event(Event::new(association.clone(), EventKind::Connected))).await.unwrap();
+        client_tx.send(Zip::build_data(Package::new(association.clone(), Bytes::from("hello")))).await.unwrap();
+
+        match recv(&mut client_rx).await {
+            Zip::Event(event) => {
+                assert_eq!(event.get_association(), &association);
+                assert_eq!(event.get_kind(), &EventKind::Connected);
+                assert_eq!(event.get_peer_subject().as_deref(), Some("CN=pig-node"));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        match recv(&mut server_rx).await {
+            Zip::Event(event) => {
+                assert_eq!(event.get_kind(), &EventKind::Connected);
+                assert_eq!(event.get_association().get_protocol(), &Protocol::TLS);
+                assert_eq!(event.get_peer_subject().as_deref(), Some("CN=pig-node"));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        match recv(&mut server_rx).await {
+            Zip::Data(package) => assert_eq!(package.get_data(), &Bytes::from("hello")),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_failure() {
+        let (_dir, tls) = gen_certs("handshake");
+        let server_addr = SocketAddr::from_str("127.0.0.1:38445").unwrap();
+        let (_server_tx, mut server_rx, handle) = net::init_net(Protocol::TLS, server_addr, listen_conf(&tls)).await.unwrap();
+
+        //非TLS客户端：握手失败通知Rejected(Handshake)并计数
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        stream.write_all(b"OPTIONS sip:pig SIP/2.0\r\n\r\n").await.unwrap();
+        match recv(&mut server_rx).await {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::Rejected(Reject::Handshake)),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(*handle.stats().snapshot().get_rejected(), 1);
+
+        //未配置ca_file时不能要求对端证书
+        let conf = TlsConf { ca_file: None, ..tls };
+        assert!(TlsContext::build(&conf).is_err());
+    }
+}
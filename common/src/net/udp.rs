@@ -1,16 +1,39 @@
 use tokio::sync::mpsc::{Sender, Receiver};
 use log::{debug, error, info, warn};
 use crate::exception::{GlobalResult, TransError};
-use crate::net::state::{Zip, Gate, GateListener, GateAccept, SOCKET_BUFFER_SIZE, Association, Protocol, Package};
+use crate::net::state::{Zip, Gate, GateListener, GateAccept, Association, Protocol, Package, Event, EventKind, ListenConf};
+use crate::net::handle::{Signal, Stage};
+use crate::net::inbound::Inbound;
+use crate::net::ratelimit::Verdict;
+use crate::net::{multicast, proxy, sockopt};
+use crate::net::admission::Reject;
+use crate::net::proxy::ProxyPeers;
+use socket2::{SockAddr, SockRef};
+use tokio::io::Interest;
 use tokio::net::UdpSocket;
-use std::net::SocketAddr;
-use bytes::Bytes;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use dashmap::DashMap;
+use bytes::{Buf, BytesMut};
 use tokio::io;
 
 //监听，将socket句柄发送出去
 pub async fn listen(gate: Gate) -> GlobalResult<GateListener> {
     let local_addr = gate.get_local_addr().clone();
-    let socket = UdpSocket::bind(local_addr).await.hand_log(|msg| error!("{msg}"))?;
+    let socket = match gate.get_conf().get_socket() {
+        None => UdpSocket::bind(local_addr).await.hand_log(|msg| error!("{msg}"))?,
+        Some(options) => {
+            let std_udp_socket = options.bind_udp(local_addr).hand_log(|msg| error!("{msg}"))?;
+            std_udp_socket.set_nonblocking(true).hand_log(|msg| error!("{msg}"))?;
+            UdpSocket::from_std(std_udp_socket).hand_log(|msg| error!("{msg}"))?
+        }
+    };
+    configure(&socket, &local_addr, gate.get_conf()).hand_log(|msg| error!("{msg}"))?;
     let gate_listener = GateListener::build_udp(gate, socket);
     debug!("开始监听 UDP 地址： {}", local_addr);
     Ok(gate_listener)
@@ -19,10 +42,21 @@ pub fn listen_by_std(gate: Gate,std_udp_socket:std::net::UdpSocket) -> GlobalRes
     debug!("tokio监听 UDP 地址： {}", gate.get_local_addr());
     std_udp_socket.set_nonblocking(true).hand_log(|msg| error!("{msg}"))?;
     let socket = UdpSocket::from_std(std_udp_socket).hand_log(|msg| error!("{msg}"))?;
+    configure(&socket, gate.get_local_addr(), gate.get_conf()).hand_log(|msg| error!("{msg}"))?;
     let gate_listener = GateListener::build_udp(gate, socket);
     Ok(gate_listener)
 }
 
+//监听建立后设置广播、组播与IP_PKTINFO
+pub(crate) fn configure(udp_socket: &UdpSocket, local_addr: &SocketAddr, conf: &ListenConf) -> io::Result<()> {
+    let socket = SockRef::from(udp_socket);
+    multicast::configure(&socket, local_addr, conf)?;
+    if *conf.get_pktinfo() {
+        sockopt::set_pktinfo(&socket, local_addr)?;
+    }
+    Ok(())
+}
+
 //将socket句柄包装发送出去
 pub async fn accept(gate: Gate, udp_socket: UdpSocket, accept_tx: Sender<GateAccept>) -> GlobalResult<()> {
     let gate_accept = GateAccept::accept_udp(gate, udp_socket);
@@ -30,27 +64,140 @@ pub async fn accept(gate: Gate, udp_socket: UdpSocket, accept_tx: Sender<GateAcc
     Ok(())
 }
 
-pub async fn read(local_addr: SocketAddr, udp_socket: &UdpSocket, tx: Sender<Zip>) {
+//分片监听(SO_REUSEPORT)的路由表：记录对端最近一次由哪个socket接收，回复经同一socket发出
+//内核按四元组散列分配socket，同一对端的分片稳定；空闲超过ROUTE_IDLE_TTL的表项定期移除，之后按地址散列
+#[derive(Debug, Clone)]
+pub(crate) struct Routes {
+    table: Arc<DashMap<SocketAddr, (usize, Instant)>>,
+    shards: usize,
+    next_prune: Arc<Mutex<Instant>>,
+}
+
+//路由表项空闲超时
+const ROUTE_IDLE_TTL: Duration = Duration::from_secs(300);
+//路由表清理间隔
+const ROUTE_PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+impl Routes {
+    pub(crate) fn new(shards: usize) -> Self {
+        Self { table: Arc::new(DashMap::new()), shards: shards.max(1), next_prune: Arc::new(Mutex::new(Instant::now() + ROUTE_PRUNE_INTERVAL)) }
+    }
+
+    fn record(&self, remote_addr: SocketAddr, shard: usize) {
+        let now = Instant::now();
+        self.table.insert(remote_addr, (shard, now));
+        self.prune(now);
+    }
+
+    fn prune(&self, now: Instant) {
+        {
+            let mut next_prune = self.next_prune.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if now < *next_prune {
+                return;
+            }
+            *next_prune = now + ROUTE_PRUNE_INTERVAL;
+        }
+        self.table.retain(|_, (_, last)| now.saturating_duration_since(*last) < ROUTE_IDLE_TTL);
+    }
+
+    //未收到过数据或表项已过期的对端按地址散列
+    pub(crate) fn lookup(&self, remote_addr: &SocketAddr) -> usize {
+        match self.table.get(remote_addr) {
+            Some(route) => route.0,
+            None => {
+                let mut hasher = DefaultHasher::new();
+                remote_addr.hash(&mut hasher);
+                hasher.finish() as usize % self.shards
+            }
+        }
+    }
+}
+
+//数据报读入复用的缓冲后切出，不做拷贝；超过buffer_size的数据报被截断，丢弃并通知Truncated
+//不满足接入控制网段的数据报丢弃并通知Rejected；超出限速的数据报丢弃(UDP不断开)
+//route:分片监听时记录对端所在的分片
+//配置proxy_protocol时，可信代理的数据报去除PROXY v2头，按客户端地址通知，并在proxied中记录回复经由的代理
+pub async fn read(local_addr: SocketAddr, udp_socket: &UdpSocket, tx: Inbound, mut signal: Signal, conf: Arc<ListenConf>, route: Option<(Routes, usize)>, proxied: Option<ProxyPeers>) {
+    let buffer_size = conf.buffer_size();
+    //多预留1字节，读满即表示数据报超长
+    let mut buf = BytesMut::with_capacity(buffer_size + 1);
     loop {
-        let _ = udp_socket.readable().await;
-        let mut buf = [0u8; SOCKET_BUFFER_SIZE];
-        match udp_socket.try_recv_from(&mut buf) {
-            Ok((len, remote_addr)) => {
-                if len != 0 {
+        tokio::select! {
+            _ = udp_socket.readable() => {}
+            _ = signal.stopping() => {
+                debug!("【UDP】停止监听 => {}", local_addr);
+                break;
+            }
+        }
+        buf.clear();
+        buf.reserve(buffer_size + 1);
+        let res = if *conf.get_pktinfo() {
+            udp_socket.try_io(Interest::READABLE, || recv_pktinfo(udp_socket, &mut buf, buffer_size + 1))
+        } else {
+            udp_socket.try_recv_buf_from(&mut buf).map(|(len, peer_addr)| (len, peer_addr, None))
+        };
+        match res {
+            Ok((len, peer_addr, local_ip)) => {
+                let (remote_addr, header_len) = match conf.get_proxy_protocol() {
+                    Some(proxy_conf) if proxy_conf.is_trusted(&peer_addr.ip()) => match proxy::parse_v2(&buf) {
+                        Ok(Some(header)) => {
+                            let remote_addr = header.get_source().unwrap_or(peer_addr);
+                            if let Some(proxied) = &proxied {
+                                proxied.record(remote_addr, peer_addr);
+                            }
+                            (remote_addr, *header.get_len())
+                        }
+                        res => {
+                            warn!("【UDP proxy header invalid】 【Local_addr = {}】 【Proxy_addr = {}】 【err = {:?}】", local_addr, peer_addr, res.err());
+                            tx.stats().record_rejected();
+                            let zip = Zip::build_event(Event::new(Association::new(local_addr, peer_addr, Protocol::UDP), EventKind::Rejected(Reject::InvalidProxy)));
+                            let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                            continue;
+                        }
+                    },
+                    _ => (peer_addr, 0),
+                };
+                let association = Association::new(local_addr, remote_addr, Protocol::UDP);
+                if let Some(Err(reject)) = conf.get_admission().as_ref().map(|admission| admission.check_ip(&remote_addr.ip())) {
+                    warn!("【UDP datagram rejected】 【Local_addr = {}】 【Remote_addr = {}】 【Reason = {:?}】", local_addr, remote_addr, reject);
+                    tx.stats().record_rejected();
+                    let zip = Zip::build_event(Event::new(association, EventKind::Rejected(reject)));
+                    let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                } else if len > buffer_size {
+                    warn!("【UDP datagram truncated】 【Local_addr = {}】 【Remote_addr = {}】 【buffer_size = {}】",
+                            local_addr,
+                            remote_addr,
+                            buffer_size
+                            );
+                    let zip = Zip::build_event(Event::new(association, EventKind::Truncated));
+                    let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                } else if len > header_len && tx.throttle(&association).await == Verdict::Pass {
+                    let len = len - header_len;
                     debug!("【UDP read success】 【Local_addr = {}】 【Remote_addr = {}】 【len = {}】",
                             local_addr.to_string(),
                             remote_addr.to_string(),
                             len
                             );
-                    let association = Association::new(local_addr, remote_addr, Protocol::UDP);
-                    let zip = Zip::build_data(Package::new(association, Bytes::copy_from_slice(&buf[..len])));
-                    let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                    tx.stats().record_in(&association, len, 1);
+                    if let Some((routes, shard)) = &route {
+                        routes.record(remote_addr, *shard);
+                    }
+                    buf.advance(header_len);
+                    let mut package = Package::new(association, buf.split().freeze());
+                    package.local_ip = local_ip;
+                    let zip = Zip::build_data(package);
+                    tx.deliver(zip).await;
                 }
             }
 
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                 continue;
             }
+            //先前发送触发的ICMP不可达，与本端socket无关
+            Err(ref e) if matches!(e.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset) => {
+                debug!("【UDP read ignore】 【Local_addr = {}】 【err = {:?}】", local_addr, e);
+                continue;
+            }
             Err(err) => {
                 warn!("【UDP read failure】 【Local_addr = {}】 【err = {:?}】",
                             local_addr.to_string(),
@@ -62,36 +209,301 @@ pub async fn read(local_addr: SocketAddr, udp_socket: &UdpSocket, tx: Sender<Zip
     }
 }
 
-pub async fn write(udp_socket: &UdpSocket, mut rx: Receiver<Zip>) {
-    while let Some(zip) = rx.recv().await {
-        let _ = udp_socket.writable().await;
+//recvmsg读取数据报，并从IP_PKTINFO/IPV6_PKTINFO取得目的地址；双栈socket上的IPv4目的地址转为IPv4
+fn recv_pktinfo(udp_socket: &UdpSocket, buf: &mut BytesMut, capacity: usize) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    let spare = &mut buf.spare_capacity_mut()[..capacity];
+    let mut iov = libc::iovec { iov_base: spare.as_mut_ptr() as *mut libc::c_void, iov_len: spare.len() };
+    let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    //cmsg需按cmsghdr对齐
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    let len = unsafe { libc::recvmsg(udp_socket.as_raw_fd(), &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = len as usize;
+    //recvmsg已写入len字节
+    unsafe { buf.set_len(len) };
+    let peer_addr = unsafe { SockAddr::new(name, msg.msg_namelen) }.as_socket()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported peer address"))?;
+    let mut local_ip = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let (level, kind, data) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type, libc::CMSG_DATA(cmsg)) };
+        match (level, kind) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let info = unsafe { std::ptr::read_unaligned(data as *const libc::in_pktinfo) };
+                local_ip = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr))));
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let info = unsafe { std::ptr::read_unaligned(data as *const libc::in6_pktinfo) };
+                local_ip = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)).to_canonical());
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((len, peer_addr, local_ip))
+}
+
+//发送失败通知WriteError并继续发送；监听关闭：Draining时发送完已排队的数据后退出
+//经代理接入的对端，回复发往记录的代理地址
+pub async fn write(udp_socket: &UdpSocket, mut rx: Receiver<Zip>, tx: Inbound, mut signal: Signal, proxied: Option<ProxyPeers>) {
+    let mut draining = false;
+    loop {
+        let zip = tokio::select! {
+            zip = rx.recv() => zip,
+            stage = signal.stopping(), if !draining => {
+                match stage {
+                    Stage::Draining => {
+                        draining = true;
+                        rx.close();
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+        };
+        let Some(zip) = zip else { break; };
         match zip {
             Zip::Data(package) => {
-                let bytes = package.get_data();
-                let local_addr = package.get_association().get_local_addr();
-                let remote_addr = package.get_association().get_remote_addr();
-                match udp_socket.try_send_to(&*bytes, *package.get_association().get_remote_addr()) {
+                let association = package.get_association().clone();
+                let bytes = package.get_owned_data();
+                //等待可写后发送，不因缓冲区满丢弃数据
+                let target = proxied.as_ref().map_or(*association.get_remote_addr(), |proxied| proxied.target(association.get_remote_addr()));
+                match udp_socket.send_to(&bytes, target).await {
                     Ok(len) => {
+                        tx.stats().record_out(&association, len, 1);
                         debug!("【UDP write success】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【len = {}】",
-                            local_addr,
-                            remote_addr,
+                            association.get_local_addr(),
+                            association.get_remote_addr(),
                             len
                             );
                     }
-                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                        continue;
-                    }
+                    //单个对端发送失败(如ICMP不可达)通知程序，不影响其他对端
                     Err(err) => {
-                        error!("【UDP write failure】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【err = {:?}】",
-                            local_addr,
-                            remote_addr,
+                        tx.stats().record_write_error(&association);
+                        warn!("【UDP write failure】 【Local_addr = {:?}】 【Remote_addr = {:?}】 【err = {:?}】",
+                            association.get_local_addr(),
+                            association.get_remote_addr(),
                             err
                             );
-                        break;
+                        let zip = Zip::build_event(Event::new(association, EventKind::WriteError(err.kind())));
+                        let _ = tx.send(zip).await.hand_log(|msg| error!("{msg}"));
                     }
                 }
             }
             Zip::Event(_event) => { info!("UDP Events are not supported") }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use crate::net;
+    use std::net::Ipv4Addr;
+
+    use crate::net::admission::Cidr;
+    use crate::net::multicast::{Membership, MulticastConf};
+    use crate::net::proxy::ProxyConf;
+    use crate::net::sockopt::SocketOptions;
+    use crate::net::ratelimit::{LimitAction, LimitKey, RateLimitConf};
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn test_routes_prune() {
+        let routes = Routes::new(4);
+        let remote_addr = SocketAddr::from_str("10.0.0.1:5060").unwrap();
+        let hashed = routes.lookup(&remote_addr);
+        let shard = (hashed + 1) % 4;
+        routes.record(remote_addr, shard);
+        assert_eq!(routes.lookup(&remote_addr), shard);
+        routes.prune(Instant::now() + ROUTE_IDLE_TTL + ROUTE_PRUNE_INTERVAL);
+        assert!(routes.table.is_empty());
+        assert_eq!(routes.lookup(&remote_addr), hashed);
+    }
+
+    #[tokio::test]
+    async fn test_write_error_keeps_writer() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38460").unwrap();
+        let (tx, mut rx, handle) = net::init_net(Protocol::UDP, local_addr, ListenConf::default()).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bad = Association::new(local_addr, SocketAddr::from_str("[::1]:5060").unwrap(), Protocol::UDP);
+        let good = Association::new(local_addr, peer.local_addr().unwrap(), Protocol::UDP);
+
+        tx.send(Zip::build_data(Package::new(bad.clone(), Bytes::from("lost")))).await.unwrap();
+        tx.send(Zip::build_data(Package::new(good.clone(), Bytes::from("hello")))).await.unwrap();
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Event(event) => {
+                assert_eq!(event.get_association(), &bad);
+                assert!(matches!(event.get_kind(), EventKind::WriteError(_)));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        let mut buf = [0u8; 16];
+        let (len, _) = time::timeout(Duration::from_secs(5), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"hello");
+        assert_eq!(*handle.stats().association(&bad).unwrap().get_write_errors(), 1);
+        let sent = handle.stats().association(&good).unwrap();
+        assert_eq!((*sent.get_packets_out(), *sent.get_bytes_out(), *sent.get_write_errors()), (1, 5, 0));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_datagram() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38461").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_buffer_size(Some(8));
+        let (_tx, mut rx, _) = net::init_net(Protocol::UDP, local_addr, conf).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        peer.send_to(b"0123456789abcdef", local_addr).await.unwrap();
+        peer.send_to(b"01234567", local_addr).await.unwrap();
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::Truncated),
+            other => panic!("unexpected {other:?}"),
+        }
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Data(package) => assert_eq!(package.get_data(), &Bytes::from("01234567")),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_drop() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38462").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_rate_limits(vec![RateLimitConf::new(1.0, Some(2.0), LimitKey::Ip, LimitAction::Drop)]);
+        let (_tx, mut rx, handle) = net::init_net(Protocol::UDP, local_addr, conf).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for i in 0..5u8 {
+            peer.send_to(&[i], local_addr).await.unwrap();
+        }
+        for i in 0..2u8 {
+            match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+                Zip::Data(package) => assert_eq!(package.get_data()[..], [i]),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+        assert!(time::timeout(Duration::from_millis(200), rx.recv()).await.is_err());
+        assert_eq!(*handle.stats().snapshot().get_limited(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol() {
+        let local_addr = SocketAddr::from_str("127.0.0.1:38464").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_proxy_protocol(Some(ProxyConf::new(vec![Cidr::from_str("127.0.0.0/8").unwrap()], None).unwrap()));
+        let (tx, mut rx, _) = net::init_net(Protocol::UDP, local_addr, conf).await.unwrap();
+        let proxy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut datagram = b"\r\n\r\n\0\r\nQUIT\n\x21\x12\x00\x0c".to_vec();
+        datagram.extend_from_slice(&[203, 0, 113, 7, 127, 0, 0, 1]);
+        datagram.extend_from_slice(&5060u16.to_be_bytes());
+        datagram.extend_from_slice(&38464u16.to_be_bytes());
+        datagram.extend_from_slice(b"OPTIONS");
+        proxy.send_to(&datagram, local_addr).await.unwrap();
+        let association = match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Data(package) => {
+                assert_eq!(package.get_data(), &Bytes::from("OPTIONS"));
+                package.get_association().clone()
+            }
+            other => panic!("unexpected {other:?}"),
+        };
+        assert_eq!(association.get_remote_addr(), &SocketAddr::from_str("203.0.113.7:5060").unwrap());
+        //回复经原代理发出
+        tx.send(Zip::build_data(Package::new(association, Bytes::from("200")))).await.unwrap();
+        let mut buf = [0u8; 8];
+        let len = time::timeout(Duration::from_secs(5), proxy.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"200");
+
+        proxy.send_to(b"OPTIONS", local_addr).await.unwrap();
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Event(event) => assert_eq!(event.get_kind(), &EventKind::Rejected(Reject::InvalidProxy)),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    fn has_ipv6_loopback() -> bool {
+        std::net::UdpSocket::bind("[::1]:0").is_ok()
+    }
+
+    //经回环网卡收发一次组播数据报，验证环境支持
+    fn has_loopback_multicast() -> bool {
+        let probe = || -> std::io::Result<bool> {
+            let group = Ipv4Addr::new(239, 255, 0, 3);
+            let receiver = std::net::UdpSocket::bind("0.0.0.0:0")?;
+            receiver.join_multicast_v4(&group, &Ipv4Addr::LOCALHOST)?;
+            receiver.set_read_timeout(Some(Duration::from_millis(500)))?;
+            let sender = std::net::UdpSocket::bind("127.0.0.1:0")?;
+            SockRef::from(&sender).set_multicast_if_v4(&Ipv4Addr::LOCALHOST)?;
+            sender.send_to(b"probe", (group, receiver.local_addr()?.port()))?;
+            let mut buf = [0u8; 8];
+            Ok(receiver.recv(&mut buf).is_ok_and(|len| &buf[..len] == b"probe"))
+        };
+        probe().unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn test_pktinfo_dual_stack() {
+        //环境不支持IPv6回环时跳过
+        if !has_ipv6_loopback() {
+            return;
+        }
+        let local_addr = SocketAddr::from_str("[::]:38465").unwrap();
+        let mut conf = ListenConf::default();
+        conf.set_pktinfo(true);
+        conf.set_socket(Some(SocketOptions { only_v6: Some(false), ..Default::default() }));
+        let (_tx, mut rx, _) = net::init_net(Protocol::UDP, local_addr, conf).await.unwrap();
+        for (peer_addr, target) in [("127.0.0.1:0", "127.0.0.1:38465"), ("[::1]:0", "[::1]:38465")] {
+            let peer = UdpSocket::bind(peer_addr).await.unwrap();
+            peer.send_to(b"ping", target).await.unwrap();
+            match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+                Zip::Data(package) => {
+                    assert_eq!(package.get_data(), &Bytes::from("ping"));
+                    assert_eq!(package.get_local_ip(), &Some(SocketAddr::from_str(target).unwrap().ip()));
+                    assert_eq!(package.get_association().get_remote_addr().ip().to_canonical(), peer.local_addr().unwrap().ip());
+                }
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multicast() {
+        //环境不支持回环组播时跳过
+        if !has_loopback_multicast() {
+            return;
+        }
+        let local_addr = SocketAddr::from_str("0.0.0.0:38463").unwrap();
+        let group = Ipv4Addr::new(239, 255, 0, 1);
+        let mut conf = ListenConf::default();
+        conf.set_broadcast(true);
+        conf.set_multicast(Some(MulticastConf::new(vec![Membership::v4(group, Some(Ipv4Addr::LOCALHOST))], Some(1), Some(true))));
+        let (_tx, mut rx, handle) = net::init_net(Protocol::UDP, local_addr, conf).await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        SockRef::from(&peer).set_multicast_if_v4(&Ipv4Addr::LOCALHOST).unwrap();
+        peer.send_to(b"hello", (group, 38463)).await.unwrap();
+        match time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap() {
+            Zip::Data(package) => assert_eq!(package.get_data(), &Bytes::from("hello")),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let other = Membership::v4(Ipv4Addr::new(239, 255, 0, 2), Some(Ipv4Addr::LOCALHOST));
+        handle.join_multicast(&other).unwrap();
+        handle.leave_multicast(&other).unwrap();
+        assert!(handle.leave_multicast(&other).is_err());
+        handle.shutdown(false).await;
+        assert!(handle.join_multicast(&other).is_err());
+    }
+}
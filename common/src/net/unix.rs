@@ -0,0 +1,338 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use log::{debug, error, info, warn};
+use tokio::net::{UnixDatagram, UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time;
+
+use exception::{GlobalError, GlobalResult, TransError};
+use crate::net::core::recv_output;
+use crate::net::handle::{NetHandle, Signal, Stage};
+use crate::net::inbound::Inbound;
+use crate::net::ratelimit::{LimitKey, Verdict};
+use crate::net::registry::Registry;
+use crate::net::state::{Association, Event, EventKind, ListenConf, Package, PeerCred, Protocol, UnixPeer, Zip, ACCEPT_BACKOFF_MIN, ACCEPT_BACKOFF_MAX};
+use crate::net::tcp;
+
+//流式连接序号
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+//启动Unix域socket监听；path为残留的socket文件时先移除，监听关闭后删除
+pub async fn listen(protocol: Protocol, path: &Path, conf: ListenConf) -> GlobalResult<(Sender<Zip>, Receiver<Zip>, NetHandle)> {
+    check_conf(&conf)?;
+    let conf = Arc::new(conf);
+    remove_stale(path)?;
+    let (handle, signal) = NetHandle::new(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0), protocol.clone());
+    let capacity = conf.channel_capacity();
+    //socket 读数据通道 input
+    let (input_tx, input_rx) = mpsc::channel(capacity);
+    //socket 写数据通道 output
+    let (output_tx, output_rx) = mpsc::channel(capacity);
+    handle.stats().watch_channels(input_tx.downgrade(), output_tx.downgrade());
+    let input = Inbound::new(input_tx, *conf.get_overload(), capacity, handle.stats().clone())
+        .with_rate_limits(conf.get_rate_limits())?;
+    let path = path.to_path_buf();
+    match protocol {
+        Protocol::UNIX => {
+            let listener = UnixListener::bind(&path).hand_log(|msg| error!("{msg}"))?;
+            debug!("开始监听 UNIX 地址： {}", path.display());
+            run_stream(listener, path, input, output_rx, conf, signal, handle.registry().clone());
+        }
+        Protocol::UNIXGRAM => {
+            let socket = Arc::new(UnixDatagram::bind(&path).hand_log(|msg| error!("{msg}"))?);
+            debug!("开始监听 UNIXGRAM 地址： {}", path.display());
+            let (read_socket, read_path, read_input, read_signal) = (socket.clone(), path.clone(), input.clone(), signal.clone());
+            let reader = tokio::spawn(async move {
+                read_datagram(&read_socket, read_path, read_input, read_signal, conf).await;
+            });
+            let writer = tokio::spawn(async move {
+                write_datagram(&socket, output_rx, input, signal).await;
+            });
+            //读写均退出后删除socket文件，读取失败时写任务仍可回复
+            tokio::spawn(async move {
+                let _ = reader.await;
+                let _ = writer.await;
+                let _ = std::fs::remove_file(&path);
+            });
+        }
+        _ => {
+            return Err(GlobalError::new_sys_error("init_unix supports UNIX and UNIXGRAM only", |msg| error!("{msg}")));
+        }
+    }
+    Ok((output_tx, input_rx, handle))
+}
+
+//UNIX对端地址为0.0.0.0:0占位，按IP的限速与接入控制会使所有本地对端共用一个名额，启动时拒绝
+fn check_conf(conf: &ListenConf) -> GlobalResult<()> {
+    if conf.get_rate_limits().iter().any(|limit| limit.key() == LimitKey::Ip) {
+        return Err(GlobalError::new_sys_error("rate limit key ip is not supported by UNIX/UNIXGRAM, use association instead", |msg| error!("{msg}")));
+    }
+    if conf.get_admission().as_ref().is_some_and(|admission| admission.per_ip()) {
+        return Err(GlobalError::new_sys_error("admission max_per_ip/allow/deny is not supported by UNIX/UNIXGRAM", |msg| error!("{msg}")));
+    }
+    Ok(())
+}
+
+//仅移除socket类型的文件，避免误删
+fn remove_stale(path: &Path) -> GlobalResult<()> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(GlobalError::new_sys_error(&format!("{} exists and is not a socket", path.display()), |msg| error!("{msg}")));
+        }
+        std::fs::remove_file(path).hand_log(|msg| error!("{msg}"))?;
+    }
+    Ok(())
+}
+
+//接入流式连接并按Zip上的association分发输出，连接读写复用tcp::read/tcp::write
+//accept失败按tcp的分类处理：单个连接的错误跳过，资源耗尽时退避重试并通知一次AcceptError
+fn run_stream(listener: UnixListener, path: PathBuf, input: Inbound, output: Receiver<Zip>, conf: Arc<ListenConf>, signal: Signal, registry: Registry) {
+    let (accept_input, accept_registry, mut accept_signal) = (input.clone(), registry.clone(), signal.clone());
+    tokio::spawn(async move {
+        let mut backoff: Option<Duration> = None;
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = accept_signal.stopping() => {
+                    debug!("【UNIX】停止监听 => {}", path.display());
+                    break;
+                }
+            };
+            let err = match accepted {
+                Ok((stream, addr)) => {
+                    if backoff.take().is_some() {
+                        info!("【UNIX accept recovered】 【Local_path = {}】", path.display());
+                    }
+                    let peer_path = addr.as_pathname().map(Path::to_path_buf);
+                    let peer = UnixPeer::new(NEXT_ID.fetch_add(1, Ordering::Relaxed), path.clone(), peer_path, peer_cred(&stream));
+                    stream_rw(stream, Association::build_unix(Protocol::UNIX, peer), &accept_input, &conf, &accept_signal, &accept_registry).await;
+                    continue;
+                }
+                Err(err) => err,
+            };
+            if tcp::is_connection_error(&err) {
+                debug!("【UNIX accept skip】 【Local_path = {}】 【err = {:?}】", path.display(), err);
+                accept_input.stats().record_accept_error(false);
+                continue;
+            }
+            accept_input.stats().record_accept_error(true);
+            let delay = match backoff {
+                None => {
+                    error!("【UNIX accept exhausted】 【Local_path = {}】 【err = {:?}】", path.display(), err);
+                    let association = Association::build_unix(Protocol::UNIX, UnixPeer::new(0, path.clone(), None, None));
+                    let _ = accept_input.send(Zip::build_event(Event::new(association, EventKind::AcceptError(err.kind())))).await.hand_log(|msg| error!("{msg}"));
+                    ACCEPT_BACKOFF_MIN
+                }
+                Some(delay) => (delay * 2).min(ACCEPT_BACKOFF_MAX),
+            };
+            backoff = Some(delay);
+            tokio::select! {
+                _ = time::sleep(delay) => {}
+                _ = accept_signal.stopping() => {
+                    debug!("【UNIX】停止监听 => {}", path.display());
+                    break;
+                }
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+    });
+    tokio::spawn(async move {
+        let (mut output, mut signal) = (output, signal);
+        while let Some(zip) = recv_output(&mut output, &mut signal).await {
+            let association = zip.get_association();
+            match registry.get(&association) {
+                None => warn!("【UNIX】连接不存在 => {:?}", &association),
+                Some(lone_output_tx) => {
+                    let _ = lone_output_tx.send(zip).await.hand_log(|msg| error!("{msg}"));
+                }
+            }
+        }
+        for (association, lone_output_tx) in registry.entries() {
+            if lone_output_tx.send(Zip::build_event(Event::new(association.clone(), EventKind::LocallyClosed))).await.is_err() {
+                tcp::close(&registry, association, EventKind::LocallyClosed, &input).await;
+            }
+        }
+    });
+}
+
+fn peer_cred(stream: &UnixStream) -> Option<PeerCred> {
+    stream.peer_cred().ok().map(|cred| PeerCred::new(cred.pid(), cred.uid(), cred.gid()))
+}
+
+async fn stream_rw(stream: UnixStream, association: Association, input: &Inbound, conf: &Arc<ListenConf>, signal: &Signal, registry: &Registry) {
+    let (lone_output_tx, lone_output_rx) = mpsc::channel(conf.channel_capacity());
+    registry.insert(association.clone(), lone_output_tx);
+    input.stats().record_accepted();
+    let _ = input.send(Zip::build_event(Event::connected(association.clone(), None))).await.hand_log(|msg| error!("{msg}"));
+    let (read, write) = stream.into_split();
+    let (read_association, read_input, read_conf, read_signal, read_registry) = (association.clone(), input.clone(), conf.clone(), signal.clone(), registry.clone());
+    let (write_done_tx, write_done_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        tcp::read(read, read_association, read_input, read_conf, read_signal, read_registry, write_done_rx).await;
+    });
+    let (write_input, write_conf, write_signal, write_registry) = (input.clone(), conf.clone(), signal.clone(), registry.clone());
+    //Unix域socket无RST，Aborted与LocallyClosed同样直接关闭
+    tokio::spawn(async move {
+        tcp::write(write, association, lone_output_rx, write_input, write_conf, write_signal, write_registry).await;
+        drop(write_done_tx);
+    });
+}
+
+//数据报按发送方路径区分对端，未绑定路径的发送方无法回复
+async fn read_datagram(socket: &UnixDatagram, path: PathBuf, tx: Inbound, mut signal: Signal, conf: Arc<ListenConf>) {
+    let buffer_size = conf.buffer_size();
+    //多预留1字节，读满即表示数据报超长
+    let mut buf = BytesMut::with_capacity(buffer_size + 1);
+    loop {
+        tokio::select! {
+            _ = socket.readable() => {}
+            _ = signal.stopping() => {
+                debug!("【UNIXGRAM】停止监听 => {}", path.display());
+                break;
+            }
+        }
+        buf.clear();
+        buf.reserve(buffer_size + 1);
+        match socket.try_recv_buf_from(&mut buf) {
+            Ok((len, addr)) => {
+                let peer = UnixPeer::new(0, path.clone(), addr.as_pathname().map(Path::to_path_buf), None);
+                let association = Association::build_unix(Protocol::UNIXGRAM, peer);
+                if len > buffer_size {
+                    warn!("【UNIXGRAM datagram truncated】 【Local_path = {}】 【buffer_size = {}】", path.display(), buffer_size);
+                    let _ = tx.send(Zip::build_event(Event::new(association, EventKind::Truncated))).await.hand_log(|msg| error!("{msg}"));
+                } else if len != 0 && tx.throttle(&association).await == Verdict::Pass {
+                    tx.stats().record_in(&association, len, 1);
+                    tx.deliver(Zip::build_data(Package::new(association, buf.split().freeze()))).await;
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                continue;
+            }
+            Err(err) => {
+                warn!("【UNIXGRAM read failure】 【Local_path = {}】 【err = {:?}】", path.display(), err);
+                break;
+            }
+        }
+    }
+}
+
+//发送失败通知WriteError并继续发送；监听关闭：Draining时发送完已排队的数据后退出
+async fn write_datagram(socket: &UnixDatagram, mut rx: Receiver<Zip>, tx: Inbound, mut signal: Signal) {
+    let mut draining = false;
+    loop {
+        let zip = tokio::select! {
+            zip = rx.recv() => zip,
+            stage = signal.stopping(), if !draining => {
+                match stage {
+                    Stage::Draining => {
+                        draining = true;
+                        rx.close();
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+        };
+        let Some(zip) = zip else { break; };
+        match zip {
+            Zip::Data(package) => {
+                let association = package.get_association().clone();
+                let bytes = package.get_owned_data();
+                let res = match association.get_unix().as_ref().and_then(|peer| peer.get_peer_path().as_ref()) {
+                    None => Err(io::Error::from(io::ErrorKind::AddrNotAvailable)),
+                    Some(peer_path) => socket.send_to(&bytes, peer_path).await,
+                };
+                match res {
+                    Ok(len) => tx.stats().record_out(&association, len, 1),
+                    Err(err) => {
+                        tx.stats().record_write_error(&association);
+                        warn!("【UNIXGRAM write failure】 【Peer = {:?}】 【err = {:?}】", association.get_unix(), err);
+                        let _ = tx.send(Zip::build_event(Event::new(association, EventKind::WriteError(err.kind())))).await.hand_log(|msg| error!("{msg}"));
+                    }
+                }
+            }
+            Zip::Event(_event) => { info!("UNIXGRAM Events are not supported") }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time;
+
+    use crate::net::admission::AdmissionConf;
+    use crate::net::ratelimit::{LimitAction, RateLimitConf};
+    use super::*;
+
+    async fn recv(rx: &mut Receiver<Zip>) -> Zip {
+        time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unix_stream() {
+        let path = std::env::temp_dir().join(format!("net-unix-{}.sock", std::process::id()));
+        let (tx, mut rx, handle) = crate::net::init_unix(Protocol::UNIX, &path, ListenConf::default()).await.unwrap();
+        let mut peer = UnixStream::connect(&path).await.unwrap();
+        let association = match recv(&mut rx).await {
+            Zip::Event(event) => {
+                assert_eq!(event.get_kind(), &EventKind::Connected);
+                event.get_association().clone()
+            }
+            other => panic!("unexpected {other:?}"),
+        };
+        let unix = association.get_unix().clone().unwrap();
+        assert_eq!(unix.get_local_path(), &path);
+        assert_eq!(unix.get_cred().unwrap().get_pid(), &Some(std::process::id() as i32));
+
+        peer.write_all(b"ping").await.unwrap();
+        match recv(&mut rx).await {
+            Zip::Data(package) => assert_eq!(package.get_data(), &Bytes::from("ping")),
+            other => panic!("unexpected {other:?}"),
+        }
+        tx.send(Zip::build_data(Package::new(association, Bytes::from("pong")))).await.unwrap();
+        let mut buf = [0u8; 4];
+        peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        time::timeout(Duration::from_secs(5), handle.shutdown(false)).await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_unix_datagram() {
+        let path = std::env::temp_dir().join(format!("net-unixgram-{}.sock", std::process::id()));
+        let peer_path = std::env::temp_dir().join(format!("net-unixgram-peer-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&peer_path);
+        let mut conf = ListenConf::default();
+        conf.set_rate_limits(vec![RateLimitConf::new(10.0, None, LimitKey::Ip, LimitAction::Drop)]);
+        assert!(crate::net::init_unix(Protocol::UNIXGRAM, &path, conf).await.is_err());
+        let mut conf = ListenConf::default();
+        conf.set_admission(Some(AdmissionConf::new(None, Some(1), Vec::new(), Vec::new())));
+        assert!(crate::net::init_unix(Protocol::UNIX, &path, conf).await.is_err());
+        let (tx, mut rx, _handle) = crate::net::init_unix(Protocol::UNIXGRAM, &path, ListenConf::default()).await.unwrap();
+        let peer = UnixDatagram::bind(&peer_path).unwrap();
+        peer.send_to(b"ping", &path).await.unwrap();
+        let package = match recv(&mut rx).await {
+            Zip::Data(package) => package,
+            other => panic!("unexpected {other:?}"),
+        };
+        assert_eq!(package.get_association().get_unix().as_ref().unwrap().get_peer_path(), &Some(peer_path.clone()));
+        tx.send(Zip::build_data(Package::new(package.get_association().clone(), Bytes::from("pong")))).await.unwrap();
+        let mut buf = [0u8; 8];
+        let len = time::timeout(Duration::from_secs(5), peer.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"pong");
+        let _ = std::fs::remove_file(&peer_path);
+    }
+}
@@ -20,6 +20,10 @@ pub mod net_err {
     //网络通信错误：TCP连接错误
     pub const NET_UNINITIALIZED_ERROR_CODE: u16 = 1001;
     pub const TCP_CONNECT_ERROR_CODE: u16 = 1010;
+    //输出的association没有对应的监听
+    pub const NET_UNROUTABLE_ERROR_CODE: u16 = 1011;
+    //等待响应超时
+    pub const NET_RESPONSE_TIMEOUT_ERROR_CODE: u16 = 1012;
 }
 
 //super -> DATA_SUPPER_ERROR_CODE = 1100